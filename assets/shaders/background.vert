@@ -0,0 +1,14 @@
+#version 130
+
+uniform float time;
+
+in vec3 position;
+in vec2 uv;
+out vec2 out_uv;
+out float out_time;
+
+void main() {
+	out_uv = uv;
+	out_time = time;
+	gl_Position = vec4(position, 1.0);
+}
@@ -0,0 +1,27 @@
+#version 130
+
+uniform sampler2D scene;
+// Only the things that glow are drawn in here
+uniform sampler2D glow;
+uniform vec2 resolution;
+uniform float strength;
+
+in vec2 out_uv;
+
+void main() {
+	vec4 color = texture(scene, out_uv);
+
+	// A blur that is wide enough to be seen even on big screens
+	vec2 spacing = 3.0 * resolution.y / 720.0 / resolution;
+	vec3 light = vec3(0.0);
+	float total = 0.0;
+	for (int x = -4; x <= 4; x++) {
+		for (int y = -4; y <= 4; y++) {
+			float weight = exp(-float(x * x + y * y) / 8.0);
+			light += texture(glow, out_uv + vec2(x, y) * spacing).rgb * weight;
+			total += weight;
+		}
+	}
+
+	gl_FragColor = vec4(color.rgb + light / total * strength, color.a);
+}
@@ -0,0 +1,13 @@
+#version 130
+
+uniform sampler2D scene;
+uniform float strength;
+
+in vec2 out_uv;
+
+void main() {
+	// The strength is how many shades there are of every color
+	float shades = max(strength - 1.0, 1.0);
+	vec4 color = texture(scene, out_uv);
+	gl_FragColor = vec4(floor(color.rgb * shades + 0.5) / shades, color.a);
+}
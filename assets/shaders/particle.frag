@@ -0,0 +1,11 @@
+#version 130
+
+uniform sampler2DArray atlas;
+
+in vec3 out_uv;
+in float out_alpha;
+
+void main() {
+	// The colors are premultiplied, so everything fades out together
+	gl_FragColor = texture(atlas, out_uv) * out_alpha;
+}
@@ -0,0 +1,16 @@
+#version 130
+
+uniform mat3 model_transform;
+uniform mat3 camera_transform;
+
+in vec3 position;
+in vec3 uv;
+in float alpha;
+out vec3 out_uv;
+out float out_alpha;
+
+void main() {
+	out_uv = uv;
+	out_alpha = alpha;
+	gl_Position = vec4(camera_transform * model_transform * position, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 130
+
+in vec2 position;
+out vec2 out_uv;
+
+void main() {
+	out_uv = position * 0.5 + 0.5;
+	gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,19 @@
+#version 130
+
+uniform sampler2D scene;
+uniform vec2 resolution;
+uniform float strength;
+
+in vec2 out_uv;
+
+void main() {
+	// The colors bleed a little bit sideways, like on an old tv
+	vec2 bleed = vec2(1.0 / resolution.x, 0.0);
+	vec4 color = texture(scene, out_uv);
+	color.r = texture(scene, out_uv - bleed).r;
+	color.b = texture(scene, out_uv + bleed).b;
+
+	// Every third line of pixels is darker
+	float line = mod(floor(out_uv.y * resolution.y), 3.0) < 1.0 ? 1.0 - strength : 1.0;
+	gl_FragColor = vec4(color.rgb * line, color.a);
+}
@@ -0,0 +1,9 @@
+#version 130
+
+uniform sampler2DArray atlas;
+
+in vec3 out_uv;
+
+void main() {
+	gl_FragColor = texture(atlas, out_uv);
+}
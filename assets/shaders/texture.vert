@@ -0,0 +1,13 @@
+#version 130
+
+uniform mat3 model_transform;
+uniform mat3 camera_transform;
+
+in vec3 position;
+in vec3 uv;
+out vec3 out_uv;
+
+void main() {
+	out_uv = uv;
+	gl_Position = vec4(camera_transform * model_transform * position, 1.0);
+}
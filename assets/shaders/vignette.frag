@@ -0,0 +1,14 @@
+#version 130
+
+uniform sampler2D scene;
+uniform float strength;
+
+in vec2 out_uv;
+
+void main() {
+	vec4 color = texture(scene, out_uv);
+	// 0 in the middle and 1 in the corners
+	vec2 from_middle = out_uv - 0.5;
+	float darkness = dot(from_middle, from_middle) * 2.0 * strength;
+	gl_FragColor = vec4(color.rgb * max(1.0 - darkness, 0.0), color.a);
+}
@@ -0,0 +1,154 @@
+use crate::prelude::*;
+use crate::matrix::matrix_mul;
+use crate::graphics::PixelGrid;
+
+/// How small a tile can get, where the screen is 2 high, before the camera
+/// stops fitting the whole level on the screen and follows the player
+/// instead.
+const MIN_TILE_SIZE: f32 = 0.1;
+const MAX_ZOOM: f32 = 4.0;
+const ZOOM_STEP: f32 = 1.25;
+/// How quickly the camera catches up with what it's looking at, higher is
+/// faster.
+const FOLLOW_SPEED: f32 = 6.0;
+/// How much of the void around the level can be seen when the camera is
+/// up against an edge, in tiles.
+const EDGE_MARGIN: f32 = 1.0;
+/// How far the screen moves at most when it shakes, in tiles.
+const MAX_SHAKE: f32 = 0.3;
+/// How much shaking goes away every second.
+const SHAKE_DECAY: f32 = 2.0;
+
+pub struct Camera {
+	// The middle of the screen, in tiles. It's None until the camera has
+	// been told where to look, so that it doesn't fly in from nowhere.
+	position: Option<[f32; 2]>,
+	target: [f32; 2],
+	// 1 is as far out as the level allows, and it only goes up from there
+	zoom: f32,
+	// From 0 to 1, the screen shakes more the higher it is
+	trauma: f32,
+	time: f32,
+}
+
+impl Default for Camera {
+	fn default() -> Camera {
+		Camera {
+			position: None,
+			target: [0.0, 0.0],
+			zoom: 1.0,
+			trauma: 0.0,
+			time: 0.0,
+		}
+	}
+}
+
+impl Camera {
+	pub fn zoom_in(&mut self) {
+		self.zoom = (self.zoom * ZOOM_STEP).min(MAX_ZOOM);
+	}
+
+	pub fn zoom_out(&mut self) {
+		self.zoom = (self.zoom / ZOOM_STEP).max(1.0);
+	}
+
+	/// Shakes the screen, ``amount`` goes from 0 to 1. Shaking adds up, so
+	/// several things happening at once shake it more.
+	pub fn shake(&mut self, amount: f32) {
+		self.trauma = (self.trauma + amount).min(1.0);
+	}
+
+	pub fn update(&mut self, dt: f32) {
+		self.time += dt;
+		self.trauma = (self.trauma - SHAKE_DECAY * dt).max(0.0);
+
+		if let Some(position) = &mut self.position {
+			let t = 1.0 - (-FOLLOW_SPEED * dt).exp();
+			position[0] = lerp(position[0], self.target[0], t);
+			position[1] = lerp(position[1], self.target[1], t);
+		}
+	}
+
+	/// How big a tile is on the screen, where the screen is 2 high.
+	fn tile_size(&self, aspect: f32, level_size: [f32; 2], pixel_grid: Option<PixelGrid>) -> f32 {
+		if let Some(grid) = pixel_grid {
+			// One pixel of a texture is a whole number of pixels on the 
+			// screen, so it can only zoom in by whole steps
+			return 2.0 * grid.tile_pixels as f32 / grid.height as f32 * self.zoom.floor();
+		}
+
+		let fit_level = 1.5 * (1.0 / level_size[1]).min(aspect / level_size[0]);
+		fit_level.max(MIN_TILE_SIZE) * self.zoom
+	}
+
+	/// Makes the camera move towards ``focus``, in tiles. If the whole level
+	/// fits on the screen along an axis it stays in the middle of it instead,
+	/// and it never goes much further than the edges of the level.
+	pub fn look_at(
+		&mut self, 
+		aspect: f32, 
+		level_size: [f32; 2], 
+		pixel_grid: Option<PixelGrid>,
+		focus: [f32; 2],
+	) {
+		let tile_size = self.tile_size(aspect, level_size, pixel_grid);
+		let view_size = [2.0 * aspect / tile_size, 2.0 / tile_size];
+
+		for i in 0..2 {
+			self.target[i] = if level_size[i] + 2.0 * EDGE_MARGIN <= view_size[i] {
+				level_size[i] / 2.0
+			} else {
+				focus[i].clamp(
+					view_size[i] / 2.0 - EDGE_MARGIN,
+					level_size[i] - view_size[i] / 2.0 + EDGE_MARGIN,
+				)
+			};
+		}
+
+		if self.position.is_none() {
+			self.position = Some(self.target);
+		}
+	}
+
+	/// The ``camera_transform`` for the shaders. ``screen_offset`` moves
+	/// everything by whole screens, to slide levels in and out of view.
+	pub fn transform(
+		&self, 
+		aspect: f32, 
+		level_size: [f32; 2], 
+		pixel_grid: Option<PixelGrid>,
+		screen_offset: [f32; 2],
+	) -> [[f32; 3]; 3] {
+		let tile_size = self.tile_size(aspect, level_size, pixel_grid);
+		let [mut x, mut y] = self.position.unwrap_or(self.target);
+
+		// A lot of shaking should feel a lot worse than a little bit
+		let shake = self.trauma * self.trauma * MAX_SHAKE;
+		x += shake * (self.time * 37.0).sin();
+		y += shake * (self.time * 41.0 + 1.0).sin();
+
+		if let Some(grid) = pixel_grid {
+			// Move by whole pixels, so that the tiles line up with the 
+			// pixels of the surface
+			let pixels_per_tile = tile_size * grid.height as f32 / 2.0;
+			let snap = |pos: f32, n_pixels: u32| {
+				let half = n_pixels as f32 / 2.0;
+				((pos * pixels_per_tile - half).round() + half) / pixels_per_tile
+			};
+			x = snap(x, grid.width);
+			y = snap(y, grid.height);
+		}
+
+		let screen = [
+			[tile_size / aspect, 0.0, 0.0],
+			[0.0, tile_size, 0.0],
+			[screen_offset[0] * 2.0 * aspect, screen_offset[1] * 2.0, 1.0],
+		];
+		let view = [
+			[1.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0],
+			[-x, -y, 1.0],
+		];
+		matrix_mul(screen, view)
+	}
+}
@@ -1,6 +1,7 @@
 use crate::prelude::*;
+use crate::level::LevelOutcome;
 use crate::level_graphics::smooth_lerp_time;
-use crate::textures::UVCoords;
+use crate::textures::{UVCoords, Texture};
 use std::path::PathBuf;
 use std::fs;
 
@@ -34,6 +35,14 @@ impl GameState {
 				level_player.render(surface, graphics, aspect, dt),
 		}
 	}
+
+	/// Draws the things that glow, lined up with what ``render`` just drew.
+	pub fn render_glow(&mut self, surface: &mut impl Surface, graphics: &Graphics) {
+		match self {
+			GameState::PlayingLevel(level_player) => 
+				level_player.render_glow(surface, graphics),
+		}
+	}
 }
 
 pub struct LevelPlayer {
@@ -44,11 +53,14 @@ pub struct LevelPlayer {
 	level_graphics: LevelGraphics,
 	// @Cleanup: Make a cached LevelGraphics to cache the levelgraphics.
 	next_level_graphics: Option<(f32, LevelGraphics, Level, [f32; 2])>,
-	cached_input: Option<Direction>,
+	// One for each set of keys
+	cached_inputs: [Option<Direction>; 2],
 	hot_load_timer: f32,
 	previous_load: std::time::SystemTime,
 	update_timer: f32,
 	time: f32,
+	// If levels have to be beaten in par
+	challenge: bool,
 }
 
 impl LevelPlayer {
@@ -72,11 +84,12 @@ impl LevelPlayer {
 			level,
 			level_graphics,
 			next_level_graphics: None,
-			cached_input: None,
+			cached_inputs: [None; 2],
 			hot_load_timer: 0.0,
 			previous_load: std::time::SystemTime::now(),
 			update_timer: 0.0,
 			time: 0.0,
+			challenge: false,
 		})
 	}
 
@@ -112,7 +125,7 @@ impl LevelPlayer {
 					println!("No previous level");
 				}
 			}
-			Input::Confirm if self.level.has_won => {
+			Input::Confirm if self.level.data.outcome == LevelOutcome::Won => {
 				if self.current_level < self.levels.len() - 1 {
 					self.current_level += 1;
 					self.reload_level_dramatic(
@@ -142,14 +155,54 @@ impl LevelPlayer {
 					println!("No more levels!");
 				}
 			}
-			Input::Move(direction) => {
-				self.cached_input = Some(direction);
+			Input::Move(keys, direction) => {
+				self.cached_inputs[keys] = Some(direction);
+			}
+			Input::Grab => {
+				self.level.toggle_grab();
+			}
+			Input::SwitchPlayer => {
+				self.level.switch_player();
+			}
+			Input::ToggleChallenge => {
+				self.challenge = !self.challenge;
+				if self.challenge {
+					println!("Challenge mode! Beat every level in par");
+				} else {
+					println!("Challenge mode off");
+				}
+
+				// Start over, so that the level has the right limits
+				self.reload_level(
+					graphics, 
+					self.levels[self.current_level].clone(),
+				);
+			}
+			Input::ZoomIn => {
+				self.level_graphics.camera.zoom_in();
+			}
+			Input::ZoomOut => {
+				self.level_graphics.camera.zoom_out();
 			}
+			// The main loop takes care of these
+			Input::ToggleFrameTime | Input::TogglePixelPerfect => (),
 		}
 
 		Ok(())
 	}
 
+	pub fn render_glow(&mut self, surface: &mut impl Surface, graphics: &Graphics) {
+		// While changing levels, the old level is still in level_graphics
+		if let Some((_, ref mut next_level_graphics, ref prev_level, _)) 
+			= self.next_level_graphics 
+		{
+			self.level_graphics.render_glow(graphics, surface, prev_level);
+			next_level_graphics.render_glow(graphics, surface, &self.level);
+		} else {
+			self.level_graphics.render_glow(graphics, surface, &self.level);
+		}
+	}
+
 	pub fn render(
 		&mut self, 
 		surface: &mut impl Surface, 
@@ -158,8 +211,10 @@ impl LevelPlayer {
 		dt: f32,
 	) -> Result<(), String> {
 		self.time += dt;
+		graphics.time = self.time;
+		self.level_graphics.update(dt);
 
-		if self.level.has_won {
+		if self.level.data.outcome == LevelOutcome::Won {
 			if self.current_level < self.levels.len() - 1 {
 				self.current_level += 1;
 				self.reload_level_dramatic(
@@ -207,7 +262,7 @@ impl LevelPlayer {
 			}
 		}
 
-		graphics.draw_background_immediate(
+		graphics.draw_background(
 			surface,
 			[-1.0, -1.0, 2.0, 2.0],
 			UVCoords {
@@ -248,7 +303,7 @@ impl LevelPlayer {
 			);
 
 			if *time < 0.0 {
-				self.cached_input = None;
+				self.cached_inputs = [None; 2];
 				self.level_graphics = self.next_level_graphics.take().unwrap().1;
 			}
 			return Ok(());
@@ -266,39 +321,91 @@ impl LevelPlayer {
 		);
 
 		if self.update_timer <= 0.0 {
-			self.level_graphics.animations.clear();
+			self.level_graphics.clear_animations();
 
-			if let Some(input) = self.cached_input.take() {
+			if self.level.coop {
+				self.level.input_coop(&self.cached_inputs);
+			} else if let Some(input) = self.cached_inputs.iter().find_map(|v| *v) {
 				self.level.input(input);
 			}
+			self.cached_inputs = [None; 2];
 
 			if !self.level.data.active_events.empty() {
 				self.update_timer = 1.0;
+				let was_in_progress = 
+					self.level.data.outcome == LevelOutcome::InProgress;
 				self.level.update(&mut self.level_graphics.animations, &graphics.sounds);
+				if let LevelOutcome::Lost(reason) = self.level.data.outcome {
+					if was_in_progress {
+						println!("{}! Undo or restart the level", reason.description());
+					}
+				}
+			}
+		}
+
+		// Count down the moves in the top left corner
+		if let Some(moves_left) = self.level.moves_left() {
+			graphics.draw_number(surface, aspect, [-aspect + 0.05, 0.95], 0.12, moves_left);
+		}
+
+		// Once the level is lost and everything has stopped moving, tell the 
+		// player to undo or restart
+		if let LevelOutcome::Lost(_) = self.level.data.outcome {
+			if self.update_timer <= 0.0 {
+				let bob = (self.time * 4.0).sin() * 0.03;
+				graphics.draw_texture_immediate(
+					surface, 
+					aspect, 
+					[-aspect, -1.0, aspect, 1.0], 
+					Texture::Shade,
+				);
+				graphics.draw_texture_immediate(
+					surface, 
+					aspect, 
+					[-0.45, -0.2 + bob, -0.05, 0.2 + bob], 
+					Texture::UndoPrompt,
+				);
+				graphics.draw_texture_immediate(
+					surface, 
+					aspect, 
+					[0.05, -0.2 - bob, 0.45, 0.2 - bob], 
+					Texture::RestartPrompt,
+				);
 			}
 		}
 
 		Ok(())
 	}
 
-	fn reload_level(&mut self, graphics: &mut Graphics, level: Level) {
+	fn reload_level(&mut self, graphics: &mut Graphics, mut level: Level) {
 		if let Some((_, gfx, _, _)) = self.next_level_graphics.take() {
 			self.level_graphics = gfx;
 		}
 
+		if self.challenge {
+			level.start_challenge();
+		}
+
 		self.level = level;
 		self.level_graphics.reset(graphics, &self.level);
 	}
 
-	fn reload_level_dramatic(&mut self, graphics: &mut Graphics, level: Level, direction: [f32; 2]) {
+	fn reload_level_dramatic(&mut self, graphics: &mut Graphics, mut level: Level, direction: [f32; 2]) {
 		if let Some((_, gfx, _, _)) = self.next_level_graphics.take() {
 			self.level_graphics = gfx;
 		}
 
+		if self.challenge && !level.start_challenge() {
+			println!("This level doesn't have a par, so anything goes");
+		}
+
 		let old = std::mem::replace(
 			&mut self.level,
 			level,
 		);
+		for goal in self.level.goals.iter() {
+			println!("Goal: {}", goal.description());
+		}
 		self.next_level_graphics
 			= Some((1.0, LevelGraphics::new(graphics, &self.level), old, direction));
 	}
@@ -1,92 +1,336 @@
 use crate::prelude::*;
-use crate::textures::{Textures, UVCoords};
+use crate::textures::{Textures, Texture, UVCoords, TextureLoadingError};
 use crate::sounds::Sounds;
+use crate::shaders::Shader;
+use crate::post_processing::{self, Effect, EffectPrograms};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::fs;
+
+const ASSETS_PATH: &str = "assets.txt";
+const SETTINGS_PATH: &str = "settings.txt";
 
 pub struct Graphics {
-	pub world_texture_program: Program,
-	pub background_program: Program,
+	pub world_texture_program: Shader,
+	pub background_program: Shader,
+	pub particle_program: Shader,
+	pub effect_programs: EffectPrograms,
+	// The post processing effects from the settings, in order
+	pub effects: Vec<Effect>,
 	pub textures: Textures,
 	pub sounds: Sounds,
 	pub display: Display,
+	// Set while drawing to a surface where the pixels of the textures should
+	// line up with the pixels of the surface
+	pub pixel_grid: Option<PixelGrid>,
+	// How long the game has been running, in seconds, for animated textures
+	pub time: f32,
+	// Goes up every time the textures are reloaded, since anything that 
+	// remembers where things are in the atlas has to look again
+	pub textures_version: u32,
+	// Why the textures couldn't be loaded the last time, if they couldn't
+	pub asset_error: Option<TextureLoadingError>,
+	// Everything the textures were loaded from, to reload them when any
+	// of it changes
+	asset_files: Vec<PathBuf>,
+	assets_modified: Option<SystemTime>,
+	asset_hot_load_timer: f32,
+	settings_modified: Option<SystemTime>,
+	settings_error: Option<String>,
+
+	// The background is one quad that only has to be rewritten when the 
+	// window changes shape.
+	background_vertices: VertexBuffer<BackgroundVertex>,
+	background_indices: IndexBuffer<u32>,
+	background_quad: [BackgroundVertex; 4],
+
+	// Things drawn straight onto the screen, like numbers, are one quad at 
+	// a time, so they share a buffer that is rewritten for every one
+	immediate_vertices: VertexBuffer<TextureVertex>,
+	immediate_indices: IndexBuffer<u32>,
 }
 
 impl Graphics {
 	pub fn new(display: &Display, sounds: Sounds) -> Self {
-		println!("Compiling texture shader...");
-		let world_texture_program = Program::from_source(display, TEXTURE_VERTEX_SHADER, TEXTURE_FRAGMENT_SHADER, None).unwrap();
-		println!("Compiling background shader...");
-		let background_program = Program::from_source(display, BACKGROUND_VERTEX_SHADER, BACKGROUND_FRAGMENT_SHADER, None).unwrap();
+		let world_texture_program = load_shader!(display, "texture");
+		let background_program = load_shader!(display, "background");
+		let particle_program = load_shader!(display, "particle");
+		let effect_programs = EffectPrograms::load(display);
 
-		// @Cleanup: Don't unwrap here, silly!
-		let textures = Textures::load("assets.txt", &display).unwrap();
+		// Without textures everything is a checkerboard, but at least you can 
+		// fix assets.txt without restarting
+		let mut asset_files = Vec::new();
+		let (textures, asset_error) = 
+			match Textures::load(ASSETS_PATH, display, &mut asset_files) {
+				Ok(textures) => (textures, None),
+				Err(err) => {
+					println!("Couldn't load the textures: {}", err);
+					(Textures::fallback(display), Some(err))
+				}
+			};
+		let assets_modified = newest_modification(&asset_files);
 
-		Graphics {
+		let background_quad = [BackgroundVertex { position: [0.0; 3], uv: [0.0; 2] }; 4];
+		let background_vertices = VertexBuffer::dynamic(display, &background_quad).unwrap();
+		let background_indices = IndexBuffer::new(display,
+			index::PrimitiveType::TrianglesList,
+			&[0, 1, 2, 0, 2, 3u32],
+		).unwrap();
+
+		let immediate_vertices = VertexBuffer::empty_dynamic(display, 4).unwrap();
+		let immediate_indices = IndexBuffer::new(display,
+			index::PrimitiveType::TrianglesList,
+			&[0, 1, 2, 0, 2, 3u32],
+		).unwrap();
+
+		let (effects, settings_error) = match load_settings() {
+			Ok(effects) => (effects, None),
+			Err(err) => (Vec::new(), Some(err)),
+		};
+		let settings_modified = newest_modification(&[SETTINGS_PATH.into()]);
+
+		let graphics = Graphics {
 			sounds,
 			world_texture_program,
 			background_program,
+			particle_program,
+			effect_programs,
+			effects,
 			textures,
 			display: display.clone(),
+			pixel_grid: None,
+			time: 0.0,
+			textures_version: 0,
+			asset_error,
+			asset_files,
+			assets_modified,
+			asset_hot_load_timer: 0.0,
+			settings_modified,
+			settings_error,
+			background_vertices,
+			background_indices,
+			background_quad,
+			immediate_vertices,
+			immediate_indices,
+		};
+		graphics.report_asset_error();
+		graphics
+	}
+
+	/// Reloads the textures, shaders and settings if any of the files they 
+	/// came from have changed. Anything that can't be loaded keeps its old version.
+	pub fn hot_load_assets(&mut self, dt: f32) {
+		self.asset_hot_load_timer -= dt;
+		if self.asset_hot_load_timer > 0.0 {
+			return;
+		}
+		self.asset_hot_load_timer = 1.0;
+
+		// Don't stop at the first one, they should all be up to date
+		let mut changed = false;
+		changed |= self.world_texture_program.hot_load(&self.display);
+		changed |= self.background_program.hot_load(&self.display);
+		changed |= self.particle_program.hot_load(&self.display);
+		changed |= self.effect_programs.hot_load(&self.display);
+		changed |= self.hot_load_textures();
+		changed |= self.hot_load_settings();
+
+		if changed {
+			self.report_asset_error();
 		}
 	}
 
-	// pub fn draw_texture_immediate(&self, surface: &mut impl Surface, aspect: f32, rect: [f32; 4], texture: TextureId) {
-	// 	let uv = self.textures.get_uv(texture);
-	// 	let vertices = VertexBuffer::new(&self.display,
-	// 		&[TextureVertex {
-	// 			position: [rect[0], rect[1], 1.0],
-	// 			uv: [uv.left, uv.bottom],
-	// 		},
-	// 		TextureVertex {
-	// 			position: [rect[0], rect[3], 1.0],
-	// 			uv: [uv.left, uv.top],
-	// 		},
-	// 		TextureVertex {
-	// 			position: [rect[2], rect[3], 1.0],
-	// 			uv: [uv.right, uv.top],
-	// 		},
-	// 		TextureVertex {
-	// 			position: [rect[2], rect[1], 1.0],
-	// 			uv: [uv.right, uv.bottom],
-	// 		}]
-	// 	).unwrap();
-	// 	let indices = IndexBuffer::new(&self.display,
-	// 		index::PrimitiveType::TrianglesList,
-	// 		&[0, 1, 2, 0, 2, 3u32],
-	// 	).unwrap();
-
-	// 	surface.draw(
-	// 		&vertices,
-	// 		&indices,
-	// 		&self.world_texture_program,
-	// 		&uniform! {
-	// 			model_transform: [
-	// 				[1.0, 0.0, 0.0f32],
-	// 				[0.0, 1.0, 0.0f32],
-	// 				[0.0, 0.0, 1.0f32],
-	// 			],
-	// 			camera_transform: [
-	// 				[1.0 / aspect, 0.0, 0.0f32],
-	// 				[0.0, 1.0, 0.0f32],
-	// 				[0.0, 0.0, 1.0f32],
-	// 			],
-	// 			atlas: self.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
-	// 		},
-	// 		&DrawParameters {
-	// 			blend: Blend {
-	// 				color: BlendingFunction::Addition {
-	// 					source: LinearBlendingFactor::One,
-	// 					destination: LinearBlendingFactor::OneMinusSourceAlpha,
-	// 				},
-	// 				..Default::default()
-	// 			},
-	// 			..Default::default()
-	// 		}
-	// 	).unwrap();
-	// }
+	fn hot_load_textures(&mut self) -> bool {
+		if newest_modification(&self.asset_files) == self.assets_modified {
+			return false;
+		}
+
+		let mut asset_files = Vec::new();
+		match Textures::load(ASSETS_PATH, &self.display, &mut asset_files) {
+			Ok(textures) => {
+				println!("Reloaded the textures");
+				self.textures = textures;
+				self.textures_version += 1;
+				self.asset_error = None;
+			}
+			Err(err) => {
+				println!("Couldn't load the textures: {}", err);
+				self.asset_error = Some(err);
+			}
+		}
+
+		// assets.txt might use different files now
+		self.assets_modified = newest_modification(&asset_files);
+		self.asset_files = asset_files;
+		true
+	}
+
+	fn hot_load_settings(&mut self) -> bool {
+		let modified = newest_modification(&[SETTINGS_PATH.into()]);
+		if modified == self.settings_modified {
+			return false;
+		}
+		self.settings_modified = modified;
+
+		match load_settings() {
+			Ok(effects) => {
+				println!("Reloaded the settings");
+				self.effects = effects;
+				self.settings_error = None;
+			}
+			Err(err) => self.settings_error = Some(err),
+		}
+		true
+	}
+
+	/// The first thing that went wrong loading the textures, shaders or 
+	/// settings.
+	fn first_asset_error(&self) -> Option<String> {
+		if let Some(ref err) = self.asset_error {
+			return Some(format!("{}: {}", ASSETS_PATH, err));
+		}
+		if let Some(ref err) = self.settings_error {
+			return Some(format!("{}: {}", SETTINGS_PATH, err));
+		}
+
+		[&self.world_texture_program, &self.background_program, &self.particle_program]
+			.iter()
+			.find_map(|shader| shader.error.clone())
+			.or_else(|| self.effect_programs.error())
+	}
+
+	/// Puts the asset error in the title of the window, so that it can be 
+	/// seen without looking at the console.
+	fn report_asset_error(&self) {
+		let title = match self.first_asset_error() {
+			Some(err) => {
+				// Shader compilers like to write several lines
+				let first_line = err.lines().next().unwrap_or("");
+				format!("{} - {}", crate::WINDOW_TITLE, first_line)
+			}
+			None => crate::WINDOW_TITLE.to_string(),
+		};
+		self.display.gl_window().window().set_title(&title);
+	}
+
+	/// Draws a checkerboard along the top of the screen if the textures, 
+	/// shaders or settings couldn't be loaded, to not miss that something's 
+	/// wrong. If it's a line in assets.txt, the number of that line is put 
+	/// under it. What went wrong is in the title of the window.
+	pub fn draw_asset_error(&self, surface: &mut impl Surface, aspect: f32) {
+		if self.first_asset_error().is_none() {
+			return;
+		}
+
+		const HEIGHT: f32 = 0.05;
+		const LINE_SIZE: f32 = 0.1;
+		if let Some(line) = self.asset_error.as_ref().and_then(|v| v.line()) {
+			let line = line as u32;
+			let width = Graphics::number_width(line, LINE_SIZE);
+			self.draw_number(surface, aspect, [-width / 2.0, 1.0 - HEIGHT * 1.5], LINE_SIZE, line);
+		}
+
+		let uv = self.textures.get_missing_uv();
+		self.draw_uv_immediate(
+			surface,
+			aspect,
+			[-aspect, 1.0 - HEIGHT, aspect, 1.0],
+			UVCoords {
+				right: 2.0 * aspect / HEIGHT,
+				..uv
+			},
+		);
+	}
+
+	/// Draws a texture straight onto the screen. The rect is 
+	/// ``[left, bottom, right, top]``, where the screen goes from -1 to 1 
+	/// vertically and from -aspect to aspect horizontally.
+	pub fn draw_texture_immediate(&self, surface: &mut impl Surface, aspect: f32, rect: [f32; 4], texture: Texture) {
+		let uv = self.textures.get_animated_uv(texture, self.time);
+		self.draw_uv_immediate(surface, aspect, rect, uv);
+	}
+
+	/// Like ``draw_texture_immediate``, but with any part of the atlas.
+	pub fn draw_uv_immediate(&self, surface: &mut impl Surface, aspect: f32, rect: [f32; 4], uv: UVCoords) {
+		self.immediate_vertices.write(&[
+			TextureVertex {
+				position: [rect[0], rect[1], 1.0],
+				uv: [uv.left, uv.bottom, uv.texture],
+			},
+			TextureVertex {
+				position: [rect[0], rect[3], 1.0],
+				uv: [uv.left, uv.top, uv.texture],
+			},
+			TextureVertex {
+				position: [rect[2], rect[3], 1.0],
+				uv: [uv.right, uv.top, uv.texture],
+			},
+			TextureVertex {
+				position: [rect[2], rect[1], 1.0],
+				uv: [uv.right, uv.bottom, uv.texture],
+			},
+		]);
+
+		surface.draw(
+			&self.immediate_vertices,
+			&self.immediate_indices,
+			&self.world_texture_program,
+			&uniform! {
+				model_transform: [
+					[1.0, 0.0, 0.0f32],
+					[0.0, 1.0, 0.0f32],
+					[0.0, 0.0, 1.0f32],
+				],
+				camera_transform: [
+					[1.0 / aspect, 0.0, 0.0f32],
+					[0.0, 1.0, 0.0f32],
+					[0.0, 0.0, 1.0f32],
+				],
+				atlas: self.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
+			},
+			&DrawParameters {
+				blend: Blend {
+					color: BlendingFunction::Addition {
+						source: LinearBlendingFactor::One,
+						destination: LinearBlendingFactor::OneMinusSourceAlpha,
+					},
+					..Default::default()
+				},
+				..Default::default()
+			}
+		).unwrap();
+	}
 	
-	pub fn draw_background_immediate(&self, surface: &mut impl Surface, rect: [f32; 4], uv: UVCoords, time: f32) {
-		let vertices = VertexBuffer::new(&self.display,
-			&[BackgroundVertex {
+	/// Draws a number with its top left corner at ``pos``, in the same 
+	/// coordinates as ``draw_texture_immediate``.
+	pub fn draw_number(&self, surface: &mut impl Surface, aspect: f32, pos: [f32; 2], size: f32, number: u32) {
+		const DIGITS: [Texture; 10] = [
+			Texture::Digit0, Texture::Digit1, Texture::Digit2, 
+			Texture::Digit3, Texture::Digit4, Texture::Digit5, 
+			Texture::Digit6, Texture::Digit7, Texture::Digit8, 
+			Texture::Digit9,
+		];
+
+		for (i, digit) in number.to_string().bytes().enumerate() {
+			let left = pos[0] + i as f32 * size * DIGIT_SPACING;
+			self.draw_texture_immediate(
+				surface,
+				aspect,
+				[left, pos[1] - size, left + size, pos[1]],
+				DIGITS[(digit - b'0') as usize],
+			);
+		}
+	}
+
+	/// How wide ``draw_number`` draws a number.
+	pub fn number_width(number: u32, size: f32) -> f32 {
+		let n_digits = number.to_string().len();
+		(n_digits - 1) as f32 * size * DIGIT_SPACING + size
+	}
+
+	pub fn draw_background(&mut self, surface: &mut impl Surface, rect: [f32; 4], uv: UVCoords, time: f32) {
+		let quad = [
+			BackgroundVertex {
 				position: [rect[0], rect[1], 1.0],
 				uv: [uv.left, uv.bottom],
 			},
@@ -101,16 +345,16 @@ impl Graphics {
 			BackgroundVertex {
 				position: [rect[2], rect[1], 1.0],
 				uv: [uv.right, uv.bottom],
-			}]
-		).unwrap();
-		let indices = IndexBuffer::new(&self.display,
-			index::PrimitiveType::TrianglesList,
-			&[0, 1, 2, 0, 2, 3u32],
-		).unwrap();
+			},
+		];
+		if quad != self.background_quad {
+			self.background_vertices.write(&quad);
+			self.background_quad = quad;
+		}
 
 		surface.draw(
-			&vertices,
-			&indices,
+			&self.background_vertices,
+			&self.background_indices,
 			&self.background_program,
 			&uniform! {
 				time: time,
@@ -121,6 +365,45 @@ impl Graphics {
 		).unwrap();
 	}
 
+	/// Like ``push_texture_quad``, but for particles that can fade out.
+	pub fn push_particle_quad(&self, 
+		vertices: &mut Vec<ParticleVertex>,
+		indices: &mut Vec<u32>,
+		pos: [f32; 4], 
+		uv: UVCoords,
+		alpha: f32,
+	) {
+		let vert_index = vertices.len() as u32;
+		vertices.push(ParticleVertex {
+			position: [pos[0], pos[1], 1.0],
+			uv: [uv.left, uv.bottom, uv.texture],
+			alpha,
+		});
+		vertices.push(ParticleVertex {
+			position: [pos[0], pos[1] + pos[3], 1.0],
+			uv: [uv.left, uv.top, uv.texture],
+			alpha,
+		});
+		vertices.push(ParticleVertex {
+			position: [pos[0] + pos[2], pos[1] + pos[3], 1.0],
+			uv: [uv.right, uv.top, uv.texture],
+			alpha,
+		});
+		vertices.push(ParticleVertex {
+			position: [pos[0] + pos[2], pos[1], 1.0],
+			uv: [uv.right, uv.bottom, uv.texture],
+			alpha,
+		});
+
+		indices.push(vert_index);
+		indices.push(vert_index + 1);
+		indices.push(vert_index + 2);
+
+		indices.push(vert_index);
+		indices.push(vert_index + 2);
+		indices.push(vert_index + 3);
+	}
+
 	pub fn push_texture_quad(&self, 
 		vertices: &mut Vec<TextureVertex>,
 		indices: &mut Vec<u32>,
@@ -155,49 +438,47 @@ impl Graphics {
 	}
 }
 
-#[derive(Clone, Copy)]
-pub struct BackgroundVertex {
-	pub position: [f32; 3],
-	pub uv: [f32; 2],
+/// The settings are optional, without them there just aren't any effects. 
+/// If they're broken the old effects are kept, though.
+fn load_settings() -> Result<Vec<Effect>, String> {
+	match fs::read_to_string(SETTINGS_PATH) {
+		Ok(contents) => post_processing::parse_settings(&contents).map_err(|err| {
+			println!("Couldn't load the settings: {}", err);
+			err
+		}),
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+		Err(err) => Err(err.to_string()),
+	}
 }
 
-implement_vertex!(BackgroundVertex, position, uv);
-
-const BACKGROUND_VERTEX_SHADER: &str = r##"
-#version 130
-
-uniform float time;
-
-in vec3 position;
-in vec2 uv;
-out vec2 out_uv;
-out float out_time;
-
-void main() {
-	out_uv = uv;
-	out_time = time;
-	gl_Position = vec4(position, 1.0);
+/// When any of the files was last changed.
+pub fn newest_modification(files: &[PathBuf]) -> Option<SystemTime> {
+	files.iter()
+		.filter_map(|file| fs::metadata(file).and_then(|v| v.modified()).ok())
+		.max()
 }
-"##;
 
-const BACKGROUND_FRAGMENT_SHADER: &str = r##"
-#version 130
+/// The size of a surface in pixels, for drawing pixel art without scaling it
+/// by anything but whole numbers.
+#[derive(Clone, Copy)]
+pub struct PixelGrid {
+	pub width: u32,
+	pub height: u32,
+	// How many pixels wide a tile is
+	pub tile_pixels: u32,
+}
 
-in float out_time;
-in vec2 out_uv;
+/// How far apart the digits of a number are, relative to their size. They
+/// don't fill their whole texture, so they overlap a bit.
+const DIGIT_SPACING: f32 = 0.75;
 
-void main() {
-	float x = floor(out_uv.x);
-	float y = floor(out_uv.y);
-	float real_c = (x + y + 10.0 * sin(out_time * 0.1)) / 32.0;
-	
-	float colors = 3.0;
-	float floor_c = floor(real_c * colors) / colors;
-	float error_c = floor((real_c - floor_c) * 50.0) / 50.0;
-	float c = floor_c + floor(mod(x * 13.0 - y * 11.0, 1.0 + error_c) * colors) / colors;
-	gl_FragColor = vec4(c / 70.0 + 0.15, c / 60.0 + 0.18, c / 90.0 + 0.20, 1.0);
+#[derive(Clone, Copy, PartialEq)]
+pub struct BackgroundVertex {
+	pub position: [f32; 3],
+	pub uv: [f32; 2],
 }
-"##;
+
+implement_vertex!(BackgroundVertex, position, uv);
 
 #[derive(Clone, Copy)]
 pub struct TextureVertex {
@@ -207,30 +488,11 @@ pub struct TextureVertex {
 
 implement_vertex!(TextureVertex, position, uv);
 
-const TEXTURE_VERTEX_SHADER: &str = r##"
-#version 130
-
-uniform mat3 model_transform;
-uniform mat3 camera_transform;
-
-in vec3 position;
-in vec3 uv;
-out vec3 out_uv;
-
-void main() {
-	out_uv = uv;
-	gl_Position = vec4(camera_transform * model_transform * position, 1.0);
+#[derive(Clone, Copy)]
+pub struct ParticleVertex {
+	pub position: [f32; 3],
+	pub uv: [f32; 3],
+	pub alpha: f32,
 }
-"##;
 
-const TEXTURE_FRAGMENT_SHADER: &str = r##"
-#version 130
-
-uniform sampler2DArray atlas;
-
-in vec3 out_uv;
-
-void main() {
-	gl_FragColor = texture(atlas, out_uv);
-}
-"##;
+implement_vertex!(ParticleVertex, position, uv, alpha);
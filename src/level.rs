@@ -2,6 +2,8 @@ use crate::prelude::*;
 use crate::textures::Texture;
 use std::collections::{HashMap, HashSet, VecDeque};
 use crate::sounds::{SoundId, Sounds};
+use crate::win_condition::{self, WinCondition};
+use std::rc::Rc;
 
 #[derive(Clone, Default)]
 pub struct Level {
@@ -11,12 +13,43 @@ pub struct Level {
 
 	entity_id_ctr: u32,
 
-	pub has_won: bool,
 	pub data: LevelData,
 
 	pub undo_stack: Vec<LevelData>,
 
-	player_id: u32,
+	// Every player in the level, in the order you switch between them
+	player_ids: Vec<u32>,
+	// The index of the player that is moved by the input, unless they all
+	// move together.
+	active_player: usize,
+	// If all the players move at the same time
+	pub together: bool,
+	// If every player has their own set of keys
+	pub coop: bool,
+
+	// If the player is allowed to pull things in this level
+	pub can_pull: bool,
+	// If the player is currently holding on to the things behind them
+	pub grabbing: bool,
+
+	// If humans take random steps after the player moves
+	wander: bool,
+	// Humans that walk along a path instead of wandering. ``None`` means
+	// standing still for a turn.
+	patrols: HashMap<u32, Vec<Option<Direction>>>,
+
+	// Everything that has to be done to win the level
+	pub goals: Vec<Rc<dyn WinCondition>>,
+	// The fewest moves the level can be beaten in
+	pub par: Option<u32>,
+
+	// How many moves it takes for goop to spread to the floor next to it.
+	// Zero means that goop never spreads.
+	goop_spread: u32,
+
+	// The conveyor belts every entity has ridden since the last move, so 
+	// that a loop of belts can't carry things around forever
+	conveyor_rides: HashSet<(u32, [isize; 2])>,
 }
 
 // All the data for a level state
@@ -27,43 +60,212 @@ pub struct LevelData {
 	pub tiles: Tilemap,
 	pub n_humans: usize,
 	pub has_input: bool,
+	// Part of the level data so that undoing gets you out of a lost level.
+	pub outcome: LevelOutcome,
+	// The direction each player last moved in, if that move worked. Moving 
+	// in the same direction again gives you a run-up.
+	pub run_ups: HashMap<u32, Direction>,
+	// How many times the player has moved
+	pub turn: u32,
+	// The state of the random number generator for wandering humans. This 
+	// is part of the level data so that undoing and replaying gives the same 
+	// steps.
+	rng: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LevelOutcome {
+	#[default]
+	InProgress,
+	Won,
+	Lost(LossReason),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LossReason {
+	FellInHole,
+	HumanStuck,
+	NotEnoughHomes,
+	NotEnoughCake,
+	OutOfMoves,
+}
+
+impl LossReason {
+	pub fn description(self) -> &'static str {
+		match self {
+			LossReason::FellInHole => "Someone fell into a hole",
+			LossReason::HumanStuck => "A human is stuck in a corner",
+			LossReason::NotEnoughHomes => "There are not enough homes left",
+			LossReason::NotEnoughCake => "There is not enough cake left",
+			LossReason::OutOfMoves => "You ran out of moves",
+		}
+	}
 }
 
 impl Level {
 	pub fn several_from_string(input: &str) -> Result<Vec<Level>, String> {
-		let mut level: Level = Default::default();
 		let mut levels = Vec::new();
-		let mut making_level = false;
-		let mut has_player = false;
-
-		let lines = input.lines().rev().map(|v| v.trim());
-
-		let mut y = 0;
-		for line in lines {
-			if line.len() == 0 || line.starts_with("//") {
-				// If 
-				if making_level {
-					if !has_player {
-						return Err(format!("Expected player"));
-					}
-
-					levels.insert(
-						0,
-						std::mem::replace(&mut level, Default::default())
-					);
-					making_level = false;
-					has_player = false;
+		let mut lines = Vec::new();
+
+		// The empty line at the end makes sure the last level is finished
+		for line in input.lines().map(|v| v.trim()).chain(std::iter::once("")) {
+			if line.is_empty() || line.starts_with("//") {
+				if !lines.is_empty() {
+					levels.push(Level::from_lines(&lines)?);
+					lines.clear();
 				}
 
 				continue;
 			}
 
-			if making_level == false {
-				y = 0;
-			}
+			lines.push(line);
+		}
+
+		println!("Loaded {} levels", levels.len());
+
+		Ok(levels)
+	}
+
+	/// Parses a single level. Lines starting with a ``$`` are metadata,
+	/// the rest are the rows of the tilemap, top row first.
+	///
+	/// Metadata:
+	/// * ``$ link <plate> <door>`` Makes the characters ``plate`` and ``door``
+	///   a pressure plate and a door that are linked to each other, but to no
+	///   other plates or doors. ``_`` and ``|`` are always linked.
+	/// * ``$ pull`` Lets the player grab and pull things.
+	/// * ``$ wander [seed]`` Humans take a random step after every move.
+	/// * ``$ patrol <x> <y> <path>`` The human in column ``x`` and row ``y``
+	///   (counting from the top left) walks along ``path`` after every move.
+	///   The path is made out of ``<>^v`` for steps and ``.`` for waiting, 
+	///   and starts over when it ends.
+	/// * ``$ spread <turns>`` Goop spreads to the floor next to it every
+	///   ``turns`` moves.
+	/// * ``$ together`` All the players move at the same time, instead of
+	///   switching between them.
+	/// * ``$ goal <goal>`` Adds something that has to be done to win, see
+	///   ``win_condition::parse`` for the goals. Without any goals, every 
+	///   human has to get home.
+	/// * ``$ par <moves>`` The fewest moves the level can be beaten in. In
+	///   challenge mode, that's all the moves you get.
+	/// * ``$ wrap`` Moving off one edge of the level takes you to the
+	///   opposite edge.
+	/// * ``$ coop`` Every player is controlled with their own set of keys.
+	///   The first player is the one at the top, or the left one if they are 
+	///   on the same row. There are only two sets of keys, so there have to
+	///   be exactly two players.
+	///
+	/// Ice next to a heater (``*``) melts into water, which then dries up
+	/// into floor.
+	fn from_lines(lines: &[&str]) -> Result<Level, String> {
+		let mut level: Level = Default::default();
+
+		// Extra characters defined by the metadata. These take priority over
+		// the built in characters.
+		let mut legend = HashMap::new();
+		let mut n_channels = 1;
+		let mut patrols = Vec::new();
+
+		for line in lines.iter().filter(|v| v.starts_with('$')) {
+			let mut args = line[1..].split_whitespace();
+			match args.next() {
+				Some("link") => {
+					let (plate, door) = match (
+						args.next().map(|v| v.chars().collect::<Vec<_>>()),
+						args.next().map(|v| v.chars().collect::<Vec<_>>()),
+						args.next(),
+					) {
+						(Some(plate), Some(door), None) 
+							if plate.len() == 1 && door.len() == 1 
+							=> (plate[0], door[0]),
+						_ => return Err(format!(
+							"Expected '$ link <plate> <door>', got '{}'", line
+						)),
+					};
+
+					if n_channels == u8::MAX {
+						return Err("Too many links!".to_string());
+					}
 
-			making_level = true;
+					legend.insert(
+						plate, 
+						Tile::Plate { channel: n_channels, pressed: false },
+					);
+					legend.insert(
+						door, 
+						Tile::Door { channel: n_channels, open: false },
+					);
+					n_channels += 1;
+				}
+				Some("pull") if args.next().is_none() => level.can_pull = true,
+				Some("together") if args.next().is_none() => level.together = true,
+				Some("coop") if args.next().is_none() => level.coop = true,
+				Some("wrap") if args.next().is_none() => level.data.tiles.wrap = true,
+				Some("goal") => level.goals.push(win_condition::parse(args)?),
+				Some("par") => {
+					level.par = match (args.next().map(|v| v.parse::<u32>()), args.next()) {
+						(Some(Ok(par)), None) => Some(par),
+						_ => return Err(format!(
+							"Expected '$ par <moves>', got '{}'", line
+						)),
+					};
+				}
+				Some("wander") => {
+					let seed = match (args.next().map(|v| v.parse::<u64>()), args.next()) {
+						(None, None) => 0,
+						(Some(Ok(seed)), None) => seed,
+						_ => return Err(format!(
+							"Expected '$ wander [seed]', got '{}'", line
+						)),
+					};
+
+					level.wander = true;
+					// Xorshift gets stuck on zero
+					level.data.rng = seed ^ 0x2545_f491_4f6c_dd1d;
+				}
+				Some("patrol") => {
+					let (x, y, path) = match (
+						args.next().map(|v| v.parse::<isize>()),
+						args.next().map(|v| v.parse::<isize>()),
+						args.next(),
+						args.next(),
+					) {
+						(Some(Ok(x)), Some(Ok(y)), Some(path), None) => (x, y, path),
+						_ => return Err(format!(
+							"Expected '$ patrol <x> <y> <path>', got '{}'", line
+						)),
+					};
+
+					let mut steps = Vec::new();
+					for char_ in path.chars() {
+						steps.push(match char_ {
+							'<' => Some(Direction::Left),
+							'>' => Some(Direction::Right),
+							'^' => Some(Direction::Up),
+							'v' => Some(Direction::Down),
+							'.' => None,
+							c => return Err(format!("Unknown patrol step {}", c)),
+						});
+					}
 
+					patrols.push((x, y, steps));
+				}
+				Some("spread") => {
+					level.goop_spread = match (args.next().map(|v| v.parse::<u32>()), args.next()) {
+						(Some(Ok(turns)), None) if turns > 0 => turns,
+						_ => return Err(format!(
+							"Expected '$ spread <turns>', got '{}'", line
+						)),
+					};
+				}
+				Some(other) => return Err(format!("Unknown metadata '{}'", other)),
+				None => return Err("Empty metadata line".to_string()),
+			}
+		}
+
+		// The tilemap is stored bottom row first
+		let rows = lines.iter().filter(|v| !v.starts_with('$')).rev();
+		for (y, line) in rows.enumerate() {
 			if level.width() == 0 { 
 				level.data.tiles.width = line.len(); 
 			}else if level.width() != line.len() { 
@@ -73,19 +275,17 @@ impl Level {
 			level.data.tiles.height += 1;
 
 			for (x, char_) in line.chars().enumerate() {
+				if let Some(&tile) = legend.get(&char_) {
+					level.data.tiles.buffer.push(tile);
+					continue;
+				}
+
 				level.data.tiles.buffer.push(match char_ {
 					// Entities
 					'p' | 'P' => {
-						if has_player {
-							return 
-								Err(format!("Cannot have more than 1 player!"));
-						}
-
-						has_player = true;
-
 						level.data.entities.insert(level.entity_id_ctr, 
 							Entity::new(x as isize, y as isize, EntityKind::Player));
-						level.player_id = level.entity_id_ctr;
+						level.player_ids.push(level.entity_id_ctr);
 						level.entity_id_ctr += 1;
 						if char_.is_uppercase() { Tile::Ice } else { Tile::Floor(FloorKind::Standard) }
 					}
@@ -108,6 +308,18 @@ impl Level {
 						level.entity_id_ctr += 1;
 						if char_.is_uppercase() { Tile::Ice } else { Tile::Floor(FloorKind::Standard) }
 					}
+					'x' | 'X' => {
+						level.data.entities.insert(level.entity_id_ctr, 
+							Entity::new(x as isize, y as isize, EntityKind::HeavyCrate));
+						level.entity_id_ctr += 1;
+						if char_.is_uppercase() { Tile::Ice } else { Tile::Floor(FloorKind::Standard) }
+					}
+					'm' | 'M' => {
+						level.data.entities.insert(level.entity_id_ctr, 
+							Entity::new(x as isize, y as isize, EntityKind::Sponge));
+						level.entity_id_ctr += 1;
+						if char_.is_uppercase() { Tile::Ice } else { Tile::Floor(FloorKind::Standard) }
+					}
 
 					// Tiles
 					'.' => Tile::Floor(FloorKind::Standard),
@@ -118,20 +330,68 @@ impl Level {
 					'H' => Tile::Home,
 					'S' => Tile::SadHome,
 					'%' => Tile::Ice,
+					'_' => Tile::Plate { channel: 0, pressed: false },
+					'|' => Tile::Door { channel: 0, open: false },
+					'<' => Tile::Conveyor(Direction::Left),
+					'>' => Tile::Conveyor(Direction::Right),
+					'^' => Tile::Conveyor(Direction::Up),
+					'v' => Tile::Conveyor(Direction::Down),
+					'O' => Tile::Hole,
+					'L' => Tile::OneWay(Direction::Left),
+					'R' => Tile::OneWay(Direction::Right),
+					'U' => Tile::OneWay(Direction::Up),
+					'D' => Tile::OneWay(Direction::Down),
+					'*' => Tile::Heater,
+					'E' => Tile::Exit,
 					c => return Err(format!("Unknown character {}", c)),
 				});
 			}
+		}
 
-			y += 1;
+		if level.player_ids.is_empty() {
+			return Err("Expected player".to_string());
 		}
 
-		if making_level {
-			levels.insert(0, level);
+		// Players are in reading order, top row first and then left to right
+		let entities = &level.data.entities;
+		level.player_ids.sort_by_key(|id| {
+			let [x, y] = entities.get(id).unwrap().pos;
+			(-y, x)
+		});
+
+		if level.goals.iter().all(|v| v.is_limit()) {
+			level.goals.push(Rc::new(win_condition::HumansHome));
 		}
 
-		println!("Loaded {} levels", levels.len());
+		if level.together && level.coop {
+			return Err("A level can't be both 'together' and 'coop'".to_string());
+		}
 
-		Ok(levels)
+		// There are only two sets of keys
+		if level.coop && level.player_ids.len() != 2 {
+			return Err(format!(
+				"A 'coop' level needs exactly 2 players, not {}", 
+				level.player_ids.len(),
+			));
+		}
+
+		level.data.tiles.timers = vec![0; level.data.tiles.buffer.len()];
+
+		for (x, top_y, steps) in patrols {
+			let pos = [x, level.height() as isize - 1 - top_y];
+			let id = level.get_entity_at_tile(pos)
+				.filter(|id| level.data.entities.get(id).unwrap().kind.is_human())
+				.ok_or_else(|| format!("No human to patrol at {} {}", x, top_y))?;
+			level.patrols.insert(id, steps);
+		}
+
+		// Things may start out on top of plates or conveyor belts
+		level.update_plates();
+		let mut events = Events::new();
+		level.add_conveyor_moves(&mut events, &HashSet::new());
+		level.data.active_events = events;
+
+		Ok(level)
 	}
 
 	pub fn randomized() -> Level {
@@ -145,12 +405,14 @@ impl Level {
 		let n_ice = 5;
 
 		let mut level: Level = Default::default();
+		level.goals.push(Rc::new(win_condition::HumansHome));
 		level.data.tiles.width = width;
 		level.data.tiles.height = height;
 		level.data.tiles.buffer = vec![
 			Tile::Floor(FloorKind::Standard); 
 			width * height
 		];
+		level.data.tiles.timers = vec![0; width * height];
 
 		let mut tiles = HashSet::new();
 		for x in 0..width {
@@ -221,7 +483,7 @@ impl Level {
 		let [x, y] = tiles.next().unwrap();
 		level.data.entities.insert(level.entity_id_ctr, 
 			Entity::new(x, y, EntityKind::Player));
-		level.player_id = level.entity_id_ctr;
+		level.player_ids.push(level.entity_id_ctr);
 		level.entity_id_ctr += 1;
 
 		for _ in 0..n_goops {
@@ -263,34 +525,196 @@ impl Level {
 	}
 
 	pub fn input(&mut self, input: Direction) {
+		if self.data.outcome != LevelOutcome::InProgress {
+			return;
+		}
+
+		let player_ids = self.moving_players();
+		if player_ids.is_empty() {
+			return;
+		}
+
 		for move_ in self.data.active_events.moves.iter() {
-			if move_.entity_id == self.player_id {
+			if player_ids.contains(&move_.entity_id) {
 				return;
 			}
 		}
 
-		self.data.has_input = true;
+		self.move_players(player_ids.into_iter().map(|id| (id, input)).collect());
+	}
 
-		let entity = self.data.entities.get(&self.player_id).unwrap();
+	/// Moves every player by their own input, where the index of the input
+	/// is the index of the player. Players that are already moving ignore
+	/// their input.
+	pub fn input_coop(&mut self, inputs: &[Option<Direction>]) {
+		if self.data.outcome != LevelOutcome::InProgress {
+			return;
+		}
 
-		let is_friction_push = 
-			match self.data.tiles.get_tile(entity.pos).unwrap() {
-				Tile::Ice => false,
-				_ => true
-			};
+		let moves: Vec<_> = self.player_ids.iter()
+			.zip(inputs)
+			.filter_map(|(&id, &input)| Some((id, input?)))
+			.filter(|&(id, _)| self.data.entities.contains_key(&id))
+			.filter(|&(id, _)| 
+				!self.data.active_events.moves.iter().any(|v| v.entity_id == id))
+			.collect();
 
-		let move_ = MoveEntity {
-			is_friction_push,
-			..MoveEntity::new(self.player_id, entity.pos, input)
-		};
+		if !moves.is_empty() {
+			self.move_players(moves);
+		}
+	}
+
+	/// Adds the moves for the players, and lets everything else react to 
+	/// them. This counts as one turn no matter how many players move.
+	fn move_players(&mut self, moves: Vec<(u32, Direction)>) {
+		self.data.has_input = true;
+		self.conveyor_rides.clear();
 
 		// TODO: Only add an undo state when something actually happens.
 		self.undo_stack.push(self.data.clone());
 
-		self.data.active_events.moves.push(move_);
+		self.data.turn += 1;
+
+		for (player_id, input) in moves {
+			let entity = self.data.entities.get(&player_id).unwrap();
+
+			let is_friction_push = 
+				match self.data.tiles.get_tile(entity.pos).unwrap() {
+					Tile::Ice => false,
+					_ => true
+				};
+
+			let move_ = MoveEntity {
+				is_friction_push,
+				force: if self.data.run_ups.get(&player_id) == Some(&input) { 2 } else { 1 },
+				..MoveEntity::new(player_id, entity.pos, input)
+			};
+
+			self.data.active_events.moves.push(move_);
+
+			// The pull has to come after the move of the player, so that the
+			// pushing code sees the player moving out of the way.
+			if self.grabbing {
+				let behind = self.step(move_.from, input.opposite());
+				if let Some(id) = self.get_entity_at_tile(behind) {
+					self.data.active_events.moves.push(MoveEntity {
+						is_friction_push: true,
+						is_pull: true,
+						..MoveEntity::new(id, behind, input)
+					});
+				}
+			}
+		}
+
+		self.add_human_moves();
+	}
+
+	/// Makes the level have to be beaten in par. Returns false if the level
+	/// doesn't have a par.
+	pub fn start_challenge(&mut self) -> bool {
+		match self.par {
+			Some(par) => {
+				self.goals.push(Rc::new(win_condition::WithinMoves(par)));
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// How many more moves there are before the level is lost, if there 
+	/// is a limit. Undoing a move gives it back.
+	pub fn moves_left(&self) -> Option<u32> {
+		self.goals.iter()
+			.filter_map(|v| v.move_limit())
+			.min()
+			.map(|v| v.saturating_sub(self.data.turn))
+	}
+
+	/// The players that are still around
+	pub fn players(&self) -> impl Iterator<Item = u32> + '_ {
+		self.player_ids.iter().copied()
+			.filter(move |id| self.data.entities.contains_key(id))
+	}
+
+	/// The players that the input moves
+	fn moving_players(&self) -> Vec<u32> {
+		if self.together || self.coop {
+			self.players().collect()
+		} else {
+			self.active_player().into_iter().collect()
+		}
+	}
+
+	/// The player that is currently being controlled, if there is one.
+	pub fn active_player(&self) -> Option<u32> {
+		self.player_ids.get(self.active_player).copied()
+			.filter(|id| self.data.entities.contains_key(id))
+	}
+
+	/// The texture of an entity, while it stands still or while it moves. 
+	/// Players get their own colours, so that you can tell them apart.
+	pub fn get_entity_texture(&self, id: u32, moving: bool) -> Texture {
+		let kind = self.data.entities.get(&id).unwrap().kind;
+		match (self.player_ids.iter().position(|&v| v == id), moving) {
+			(Some(1), false) => Texture::PlayerTwo,
+			(Some(1), true) => Texture::PlayerTwoWalk,
+			(_, false) => kind.get_texture(),
+			(_, true) => kind.get_moving_texture(),
+		}
+	}
+
+	/// If there is more than one player to keep track of
+	pub fn has_several_players(&self) -> bool {
+		self.players().count() > 1
+	}
+
+	/// Switches control over to the next player
+	pub fn switch_player(&mut self) {
+		if self.together {
+			println!("All the players move together in this level");
+			return;
+		}
+
+		if self.coop {
+			println!("Every player has their own keys in this level");
+			return;
+		}
+
+		if !self.has_several_players() {
+			println!("There is no one else to switch to");
+			return;
+		}
+
+		loop {
+			self.active_player = (self.active_player + 1) % self.player_ids.len();
+			if self.active_player().is_some() {
+				break;
+			}
+		}
+	}
+
+	pub fn toggle_grab(&mut self) {
+		if !self.can_pull {
+			println!("You can't grab things in this level");
+			return;
+		}
+
+		self.grabbing = !self.grabbing;
+		if self.grabbing {
+			println!("Grabbing");
+		} else {
+			println!("Let go");
+		}
+	}
+
+	/// The position one step away from ``pos``, wrapping around the edges 
+	/// if the level does that.
+	pub fn step(&self, pos: [isize; 2], direction: Direction) -> [isize; 2] {
+		self.data.tiles.wrap_pos(MoveEntity::new(0, pos, direction).to())
 	}
 
 	pub fn tile_is_solid(&self, pos: [isize; 2]) -> bool {
+		let pos = self.data.tiles.wrap_pos(pos);
 		if pos[0] < 0 || pos[0] as usize >= self.width() || 
 			pos[1] < 0 || pos[1] as usize >= self.height()
 		{
@@ -299,6 +723,8 @@ impl Level {
 
 		match self.data.tiles.get_tile(pos).unwrap() {
 			Tile::Wall(_) => return true,
+			Tile::Door { open: false, .. } => return true,
+			Tile::Heater => return true,
 			_ => (),
 		}
 
@@ -311,7 +737,360 @@ impl Level {
 		false
 	}
 
+	/// Presses the plates that have something on top of them, and opens the
+	/// doors linked to any pressed plate. A door that something is standing in
+	/// is stuck open.
+	fn update_plates(&mut self) {
+		let mut pressed_channels = HashSet::new();
+		let mut changed = false;
+
+		for y in 0..self.height() as isize {
+			for x in 0..self.width() as isize {
+				if let Some(Tile::Plate { channel, pressed }) 
+					= self.data.tiles.get_tile([x, y]) 
+				{
+					let is_pressed = self.get_entity_at_tile([x, y]).is_some();
+					if is_pressed {
+						pressed_channels.insert(channel);
+					}
+
+					if is_pressed != pressed {
+						self.data.tiles.set_tile(
+							[x, y],
+							Tile::Plate { channel, pressed: is_pressed },
+						);
+						changed = true;
+					}
+				}
+			}
+		}
+
+		for y in 0..self.height() as isize {
+			for x in 0..self.width() as isize {
+				if let Some(Tile::Door { channel, open }) 
+					= self.data.tiles.get_tile([x, y]) 
+				{
+					let is_open = pressed_channels.contains(&channel) || 
+						(open && self.get_entity_at_tile([x, y]).is_some());
+
+					if is_open != open {
+						self.data.tiles.set_tile(
+							[x, y],
+							Tile::Door { channel, open: is_open },
+						);
+						changed = true;
+					}
+				}
+			}
+		}
+
+		if changed {
+			self.n_tile_changes += 1;
+		}
+	}
+
+	/// Moves everything standing on a conveyor belt one step along it.
+	/// Things that are already moving, or whose move failed this update, 
+	/// are left alone, so that something stuck on a belt doesn't keep the 
+	/// level updating forever. For the same reason, a belt only carries each
+	/// thing once per move, so loops of belts stop after going around once.
+	fn add_conveyor_moves(&mut self, events: &mut Events, failed_moves: &HashSet<u32>) {
+		// Sorted so that conflicts are resolved the same way every time
+		let mut ids: Vec<u32> = self.data.entities.keys().copied().collect();
+		ids.sort_unstable();
+
+		for id in ids {
+			if failed_moves.contains(&id) || 
+				events.moves.iter().any(|v| v.entity_id == id) 
+			{
+				continue;
+			}
+
+			let entity = self.data.entities.get(&id).unwrap();
+			if let Some(Tile::Conveyor(direction)) 
+				= self.data.tiles.get_tile(entity.pos) 
+			{
+				if !self.conveyor_rides.insert((id, entity.pos)) {
+					continue;
+				}
+
+				events.moves.push(MoveEntity {
+					is_friction_push: true,
+					..MoveEntity::new(id, entity.pos, direction)
+				});
+			}
+		}
+	}
+
+	/// One way tiles can only be entered in their own direction, but they
+	/// can be left in any direction.
+	fn can_enter(&self, pos: [isize; 2], direction: Direction) -> bool {
+		match self.data.tiles.get_tile(pos) {
+			Some(Tile::OneWay(one_way)) => one_way == direction,
+			_ => true,
+		}
+	}
+
+	/// Static things that will never move out of the way, unlike entities or
+	/// doors.
+	fn tile_is_wall(&self, pos: [isize; 2]) -> bool {
+		matches!(self.data.tiles.get_tile(pos), None | Some(Tile::Wall(_)) | Some(Tile::Heater))
+	}
+
+	/// An entity in a corner can never be pushed out of it again. Conveyor
+	/// belts can still carry it out though, and the player may be able to
+	/// pull it out.
+	fn is_cornered(&self, pos: [isize; 2]) -> bool {
+		if self.can_pull {
+			return false;
+		}
+
+		if let Some(Tile::Conveyor(_)) = self.data.tiles.get_tile(pos) {
+			return false;
+		}
+
+		let [x, y] = pos;
+		(self.tile_is_wall([x - 1, y]) || self.tile_is_wall([x + 1, y])) &&
+			(self.tile_is_wall([x, y - 1]) || self.tile_is_wall([x, y + 1]))
+	}
+
+	/// If there is something at ``pos`` that can never be moved again. 
+	/// Players can always walk away, and so can humans that wander or 
+	/// patrol, so they never are.
+	fn is_stuck_forever(&self, pos: [isize; 2]) -> bool {
+		match self.get_entity_at_tile(pos) {
+			Some(id) => {
+				let kind = self.data.entities.get(&id).unwrap().kind;
+				kind != EntityKind::Player &&
+					!(kind.is_human() && self.moves_by_itself(id)) &&
+					self.is_cornered(pos)
+			}
+			None => false,
+		}
+	}
+
+	/// Looks for the obvious ways of making a level impossible to beat. 
+	/// This shouldn't ever find a deadlock in a level that can still be won,
+	/// so it misses a lot of the less obvious ones. Buckets of goop blocking
+	/// the way aren't looked for at all, that's harder to get right.
+	pub fn find_deadlock(&self) -> Option<LossReason> {
+		let mut n_goop_humans = 0;
+		let mut n_cakes = 0;
+		// Cakes that can still be pushed into a sad home. Cornered cakes can
+		// still be eaten by a goopy human that walks into them, though.
+		let mut n_free_cakes = 0;
+		for (&id, entity) in self.data.entities.iter() {
+			match entity.kind {
+				EntityKind::Human | EntityKind::HumanWithGoop 
+					if self.is_cornered(entity.pos) && !self.moves_by_itself(id) => 
+					return Some(LossReason::HumanStuck),
+				EntityKind::HumanWithGoop => n_goop_humans += 1,
+				EntityKind::Cake => {
+					n_cakes += 1;
+					if !self.is_cornered(entity.pos) {
+						n_free_cakes += 1;
+					}
+				}
+				_ => (),
+			}
+		}
+
+		// A home is sealed off if nothing can ever get into it
+		let mut n_homes = 0;
+		let mut n_sad_homes = 0;
+		for y in 0..self.height() as isize {
+			for x in 0..self.width() as isize {
+				let tile = self.data.tiles.get_tile([x, y]).unwrap();
+				if tile != Tile::Home && tile != Tile::SadHome {
+					continue;
+				}
+
+				let is_sealed = [[x - 1, y], [x + 1, y], [x, y - 1], [x, y + 1]]
+					.iter()
+					.all(|&pos| self.tile_is_wall(pos) || self.is_stuck_forever(pos));
+				if is_sealed {
+					continue;
+				}
+
+				if tile == Tile::Home {
+					n_homes += 1;
+				} else {
+					n_sad_homes += 1;
+				}
+			}
+		}
+
+		let n_homeless = self.data.n_humans.saturating_sub(n_homes);
+		if n_homeless > n_sad_homes {
+			return Some(LossReason::NotEnoughHomes);
+		}
+
+		// Every sad home that's needed has to be fed, and every goopy human
+		// has to eat before it can go home.
+		if n_homeless > n_free_cakes || n_homeless + n_goop_humans > n_cakes {
+			return Some(LossReason::NotEnoughCake);
+		}
+
+		None
+	}
+
+	/// Humans take a step every time the player does, either along their 
+	/// patrol or in a random direction if the level lets them wander.
+	///
+	/// The steps are put before all the other moves, which means they run
+	/// after the player has moved and pushed things.
+	fn add_human_moves(&mut self) {
+		if !self.wander && self.patrols.is_empty() {
+			return;
+		}
+
+		let mut human_moves = Vec::new();
+		// Sorted so that the random numbers end up the same every time
+		let mut ids: Vec<u32> = self.data.entities.keys().copied().collect();
+		ids.sort_unstable();
+
+		for id in ids {
+			let entity = *self.data.entities.get(&id).unwrap();
+			if !entity.kind.is_human() || 
+				self.data.active_events.moves.iter().any(|v| v.entity_id == id) 
+			{
+				continue;
+			}
+
+			let direction = if let Some(steps) = self.patrols.get(&id) {
+				steps[(self.data.turn as usize - 1) % steps.len()]
+			} else if self.wander {
+				// Only wander to places it's possible to go to, and
+				// avoid holes. Humans aren't that silly.
+				let mut candidates = Vec::with_capacity(4);
+				for &direction in &[
+					Direction::Left, 
+					Direction::Right, 
+					Direction::Up, 
+					Direction::Down,
+				] {
+					let to = self.step(entity.pos, direction);
+					if !self.tile_is_solid(to) && self.can_enter(to, direction) && 
+						self.data.tiles.get_tile(to) != Some(Tile::Hole)
+					{
+						candidates.push(direction);
+					}
+				}
+
+				// One extra option for standing still
+				let index = self.next_random() as usize % (candidates.len() + 1);
+				candidates.get(index).copied()
+			} else {
+				None
+			};
+
+			if let Some(direction) = direction {
+				human_moves.push(MoveEntity {
+					is_friction_push: 
+						self.data.tiles.get_tile(entity.pos) != Some(Tile::Ice),
+					..MoveEntity::new(id, entity.pos, direction)
+				});
+			}
+		}
+
+		self.data.active_events.moves.splice(0..0, human_moves);
+	}
+
+	/// Spreads goop from every goopy tile to the floor next to it, and
+	/// goopifies anything standing there. Sponges keep their own tile clean.
+	fn spread_goop(&mut self, animations: &mut VecDeque<Animation>) {
+		let mut spreads = Vec::new();
+		for y in 0..self.height() as isize {
+			for x in 0..self.width() as isize {
+				match self.data.tiles.get_tile([x, y]) {
+					Some(Tile::FloorWithGoop) | Some(Tile::IceWithGoop) => (),
+					_ => continue,
+				}
+
+				for &to in &[[x - 1, y], [x + 1, y], [x, y - 1], [x, y + 1]] {
+					if !matches!(self.data.tiles.get_tile(to), Some(Tile::Floor(_))) {
+						continue;
+					}
+
+					let has_sponge = self.get_entity_at_tile(to)
+						.is_some_and(|id| self.data.entities.get(&id).unwrap().kind == EntityKind::Sponge);
+					if !has_sponge {
+						spreads.push(([x, y], to));
+					}
+				}
+			}
+		}
+
+		for (from, to) in spreads {
+			animations.push_back(Animation::SpreadGoop { from, to });
+
+			// Several goopy tiles may spread to the same floor
+			if self.data.tiles.get_tile(to) == Some(Tile::FloorWithGoop) {
+				continue;
+			}
+
+			self.data.tiles.set_tile(to, Tile::FloorWithGoop);
+			self.n_tile_changes += 1;
+
+			if let Some(id) = self.get_entity_at_tile(to) {
+				let entity = self.data.entities.get_mut(&id).unwrap();
+				let old_kind = entity.kind;
+				entity.goopify();
+				if entity.kind != old_kind {
+					animations.push_back(Animation::Goopify { entity_id: id, kind: entity.kind });
+				}
+			}
+		}
+	}
+
+	/// Melts the ice next to heaters a little, and dries up water. This 
+	/// happens once for every move.
+	fn update_timers(&mut self) {
+		let mut changed = false;
+		for y in 0..self.height() as isize {
+			for x in 0..self.width() as isize {
+				let pos = [x, y];
+				let (timer_done, new_tile) = match self.data.tiles.get_tile(pos).unwrap() {
+					Tile::Ice if [[x - 1, y], [x + 1, y], [x, y - 1], [x, y + 1]]
+						.iter()
+						.any(|&v| self.data.tiles.get_tile(v) == Some(Tile::Heater))
+						=> (MELT_TURNS, Tile::Water),
+					Tile::Water => (DRY_TURNS, Tile::Floor(FloorKind::Standard)),
+					_ => continue,
+				};
+
+				let timer = self.data.tiles.get_timer(pos) + 1;
+				if timer >= timer_done {
+					self.data.tiles.set_tile(pos, new_tile);
+				} else {
+					self.data.tiles.set_timer(pos, timer);
+				}
+				changed = true;
+			}
+		}
+
+		if changed {
+			self.n_tile_changes += 1;
+		}
+	}
+
+	/// Xorshift, so that levels don't need a whole random crate.
+	fn next_random(&mut self) -> u32 {
+		let mut x = self.data.rng;
+		x ^= x << 13;
+		x ^= x >> 7;
+		x ^= x << 17;
+		self.data.rng = x;
+		(x >> 32) as u32
+	}
+
+	/// Humans that walk around on their own can get out of corners.
+	fn moves_by_itself(&self, id: u32) -> bool {
+		self.wander || self.patrols.contains_key(&id)
+	}
+
 	fn get_entity_at_tile(&self, pos: [isize; 2]) -> Option<u32> {
+		let pos = self.data.tiles.wrap_pos(pos);
 		for (&id, entity) in self.data.entities.iter() {
 			if entity.pos == pos {
 				return Some(id);
@@ -336,7 +1115,7 @@ impl Level {
 		let mut pushing_happened = false;
 		'outer: while index < events.moves.len() {
 			let move_ = events.moves[index];
-			let to = move_.to();
+			let to = self.step(move_.from, move_.direction);
 			if let Some(id) = self.get_entity_at_tile(to) {
 				let one_self = self.data.entities.get(&move_.entity_id).unwrap();
 				let entity = self.data.entities.get(&id).unwrap();
@@ -367,12 +1146,14 @@ impl Level {
 					// The goop child eats the cake!
 					let other = self.data.entities.get_mut(&id).unwrap();
 					other.kind = EntityKind::Human;
-					let other_pos = other.pos;
+					animations.push_back(Animation::EatCake { pos: other.pos });
 					let me = self.data.entities.get_mut(&move_.entity_id).unwrap();
 					let me_pos = me.pos;
 					animations.push_back(Animation::Move {
 						from: me_pos,
-						to: other_pos,
+						// Not the position of the other one, in case the 
+						// level wraps around
+						to: move_.to(),
 						entity_id: move_.entity_id,
 						accelerate: !me.is_sliding,
 						decelerate: true,
@@ -383,6 +1164,15 @@ impl Level {
 					self.data.entities.remove(&move_.entity_id);
 					continue;
 				}
+
+				if entity.kind == EntityKind::HeavyCrate && 
+					move_.force < 2 && !one_self.is_sliding 
+				{
+					// Too heavy to push without a run-up or some help, so 
+					// the move will just fail.
+					index += 1;
+					continue;
+				}
 				
 				match (
 					self.data.tiles.get_tile(one_self.pos).unwrap(),
@@ -396,27 +1186,29 @@ impl Level {
 						animations.push_back(Animation::Move {
 							entity_id: move_.entity_id,
 							from: one_self.pos,
-							to:   entity.pos,
+							to:   move_.to(),
 							accelerate: !one_self.is_sliding,
 							decelerate: true,
 							kind: AnimationMoveKind::IceKick,
 						});
-						let move_ = MoveEntity::new(
-							id,
-							entity.pos,
-							move_.direction,
-						);
+						animations.push_back(Animation::Push { entity_id: id });
+						let move_ = MoveEntity {
+							force: move_.force,
+							..MoveEntity::new(id, entity.pos, move_.direction)
+						};
 						events.moves.push(move_);
 					}
 					(_, _) => {
 						// Just normal pushing
 						let move_ = MoveEntity {
 							is_friction_push: true,
+							force: move_.force + 1,
 							..MoveEntity::new(id, entity.pos, move_.direction)
 						};
 						events.moves.push(move_);
 						index += 1;
 						pushing_happened = true;
+						animations.push_back(Animation::Push { entity_id: id });
 					}
 				};
 
@@ -436,29 +1228,39 @@ impl Level {
 		// It's run in reverse because the moves resulting from pushing
 		// are always further back in the list, so if we reverse it those
 		// are moved first, which allows the pushers to also be moved.
+		let mut failed_moves = HashSet::new();
 		for move_ in events.moves.iter().rev() {
-			let to = move_.to();
+			// Animations go to the unwrapped position, so that things slide 
+			// off the edge instead of across the whole level.
+			let animation_to = move_.to();
+			let to = self.data.tiles.wrap_pos(animation_to);
 
-			if self.tile_is_solid(to) {
+			if self.tile_is_solid(to) || !self.can_enter(to, move_.direction) {
 				let entity = self.data.entities.get(&move_.entity_id).unwrap();
 				animations.push_back(Animation::FailedMove {
 					entity_id: move_.entity_id,
 					from: move_.from,
-					to,
+					to: animation_to,
 					accelerate: !entity.is_sliding,
 				});
+				failed_moves.insert(move_.entity_id);
+				self.data.run_ups.remove(&move_.entity_id);
 				continue;
 			}
 
+			if self.player_ids.contains(&move_.entity_id) {
+				self.data.run_ups.insert(move_.entity_id, move_.direction);
+			}
+
 			let entity = self.data.entities.get(&move_.entity_id).unwrap();
 			if entity.kind == EntityKind::BucketOfGoop {
-				match self.data.tiles.get_tile(move_.to()).unwrap() {
+				match self.data.tiles.get_tile(to).unwrap() {
 					Tile::Ice => {
-						self.data.tiles.set_tile(move_.to(), Tile::IceWithGoop);
+						self.data.tiles.set_tile(to, Tile::IceWithGoop);
 						self.n_tile_changes += 1;
 					}
 					Tile::Floor(_) => {
-						self.data.tiles.set_tile(move_.to(), Tile::FloorWithGoop);
+						self.data.tiles.set_tile(to, Tile::FloorWithGoop);
 						self.n_tile_changes += 1;
 					}
 					Tile::Home | Tile::SadHome => {
@@ -469,12 +1271,28 @@ impl Level {
 							to,
 							accelerate: !entity.is_sliding,
 						});
+						failed_moves.insert(move_.entity_id);
 						continue;
 					}
 					_ => (),
 				}
 			}
 
+			if entity.kind == EntityKind::Sponge {
+				// Soak up the goop before anything slides or gets goopified
+				let cleaned = match self.data.tiles.get_tile(to).unwrap() {
+					Tile::FloorWithGoop => Some(Tile::Floor(FloorKind::Standard)),
+					Tile::IceWithGoop => Some(Tile::Ice),
+					_ => None,
+				};
+
+				if let Some(tile) = cleaned {
+					self.data.tiles.set_tile(to, tile);
+					self.n_tile_changes += 1;
+					animations.push_back(Animation::CleanGoop { pos: to });
+				}
+			}
+
 			let entity = self.data.entities.get_mut(&move_.entity_id).unwrap();
 			match self.data.tiles.get_tile(to).unwrap() {
 				Tile::Ice => {
@@ -498,18 +1316,23 @@ impl Level {
 
 			entity.pos = to;
 
-			let mut moving_to_ice = false;
-			if self.data.tiles.get_tile(to) == Some(Tile::Ice) {
-				moving_to_ice = true;
-			}
+			// Conveyor belts keep things moving just like ice does
+			let moving_to_ice = matches!(
+				self.data.tiles.get_tile(to),
+				Some(Tile::Ice) | Some(Tile::Conveyor(_))
+			);
 
 			animations.push_back(Animation::Move {
 				entity_id: move_.entity_id,
 				from: move_.from,
-				to,
+				to: animation_to,
 				accelerate: !entity.is_sliding,
 				decelerate: !moving_to_ice,
-				kind: AnimationMoveKind::Standard,
+				kind: if move_.is_pull { 
+					AnimationMoveKind::Pull 
+				} else { 
+					AnimationMoveKind::Standard 
+				},
 			});
 			entity.is_sliding = moving_to_ice;
 		}
@@ -518,6 +1341,7 @@ impl Level {
 		let mut entities_to_remove = Vec::new();
 		for (&entity_id, entity) in self.data.entities.iter() {
 			let mut modified_tile = false;
+			let mut removal_kind = AnimationMoveKind::Apply;
 			match (entity.kind, self.data.tiles.get_tile(entity.pos).unwrap()) {
 				(EntityKind::Human, Tile::Home) => {
 					self.data.tiles.set_tile(
@@ -525,23 +1349,46 @@ impl Level {
 						Tile::Wall(WallKind::HappyHome),
 					);
 					self.data.n_humans -= 1;
-					if self.data.n_humans == 0 {
-						self.has_won = true;
-					}
 					modified_tile = true;
+					animations.push_back(Animation::FillHome { pos: entity.pos });
 				}
 				(EntityKind::Cake, Tile::SadHome) => {
 					self.data.tiles.set_tile(entity.pos, Tile::Home);
 					modified_tile = true;
 				}
-				_ => (),
+				(EntityKind::Cake, Tile::Hole) | 
+				(EntityKind::CakeWithGoop, Tile::Hole) |
+				(EntityKind::HeavyCrate, Tile::Hole) |
+				(EntityKind::Sponge, Tile::Hole) => {
+					self.data.tiles.set_tile(
+						entity.pos, 
+						Tile::Floor(FloorKind::Standard),
+					);
+					modified_tile = true;
+					removal_kind = AnimationMoveKind::Fall;
+				}
+				(EntityKind::BucketOfGoop, Tile::Hole) => {
+					self.data.tiles.set_tile(entity.pos, Tile::FloorWithGoop);
+					modified_tile = true;
+					removal_kind = AnimationMoveKind::Fall;
+				}
+				(_, Tile::Hole) => {
+					// Humans and spiders don't fill the hole, they just fall
+					// in and are never seen again.
+					self.data.outcome = 
+						LevelOutcome::Lost(LossReason::FellInHole);
+					removal_kind = AnimationMoveKind::Fall;
+				}
+				_ => continue,
 			}
 
 			if modified_tile {
 				self.n_tile_changes += 1;
+			}
 
+			{
 				let mut from = entity.pos;
-				let to = entity.pos;
+				let mut to = entity.pos;
 				let mut accelerate = false;
 				for (i, animation) in animations.iter().enumerate() {
 					if let Animation::Move { 
@@ -550,10 +1397,17 @@ impl Level {
 						accelerate: anim_accelerate, 
 						kind: AnimationMoveKind::Standard,
 						..
+					} | Animation::Move { 
+						from: anim_from, 
+						to: anim_to, 
+						accelerate: anim_accelerate, 
+						kind: AnimationMoveKind::Pull,
+						..
 					} = *animation {
-						if anim_to == from {
+						if self.data.tiles.wrap_pos(anim_to) == from {
 							accelerate = anim_accelerate;
 							from = anim_from;
+							to = anim_to;
 							animations.remove(i);
 							break;
 						}
@@ -566,7 +1420,7 @@ impl Level {
 					to,
 					accelerate,
 					decelerate: false,
-					kind: AnimationMoveKind::Apply,
+					kind: removal_kind,
 				});
 				entities_to_remove.push(entity_id);
 			}
@@ -576,6 +1430,31 @@ impl Level {
 			self.data.entities.remove(&entity);
 		}
 
+		if self.data.has_input 
+			&& self.goop_spread > 0 
+			&& self.data.turn.is_multiple_of(self.goop_spread) 
+		{
+			self.spread_goop(animations);
+		}
+
+		if self.data.has_input {
+			self.update_timers();
+		}
+
+		self.update_plates();
+		self.add_conveyor_moves(&mut new_events, &failed_moves);
+
+		if self.data.outcome == LevelOutcome::InProgress {
+			if self.goals.iter().all(|v| v.is_met(self)) {
+				self.data.outcome = LevelOutcome::Won;
+			} else if new_events.empty() {
+				// Only look for deadlocks once everything has stopped moving
+				if let Some(reason) = self.goals.iter().find_map(|v| v.has_failed(self)) {
+					self.data.outcome = LevelOutcome::Lost(reason);
+				}
+			}
+		}
+
 		if self.data.has_input {
 			sounds.play(SoundId::SpiderWalk, 0.3);
 			self.data.has_input = false;
@@ -591,11 +1470,31 @@ pub struct Tilemap {
 	pub width: usize,
 	pub height: usize,
 	pub buffer: Vec<Tile>,
+	// If the edges wrap around to the other side. Then every position is
+	// inside the tilemap.
+	pub wrap: bool,
+	// How many moves each tile has been melting or drying up for. Setting
+	// a tile resets its timer.
+	pub timers: Vec<u8>,
 }
 
 impl Tilemap {
+	/// Moves a position that's off one edge in by the opposite edge, if the 
+	/// tilemap wraps around.
+	pub fn wrap_pos(&self, pos: [isize; 2]) -> [isize; 2] {
+		if self.wrap {
+			[
+				pos[0].rem_euclid(self.width as isize), 
+				pos[1].rem_euclid(self.height as isize),
+			]
+		} else {
+			pos
+		}
+	}
+
 	pub fn get_tile(&self, pos: [isize; 2]) -> Option<Tile> {
 		debug_assert_eq!(self.buffer.len(), self.width * self.height);
+		let pos = self.wrap_pos(pos);
 		if pos[0] < 0 || pos[0] as usize >= self.width 
 			|| pos[1] < 0 || pos[1] as usize >= self.height {
 			return None;
@@ -615,6 +1514,7 @@ impl Tilemap {
 	/// If the tile is out of bounds.
 	pub fn set_tile(&mut self, pos: [isize; 2], tile: Tile) {
 		debug_assert_eq!(self.buffer.len(), self.width * self.height);
+		let pos = self.wrap_pos(pos);
 
 		if pos[0] < 0 || pos[0] as usize >= self.width 
 			|| pos[1] < 0 || pos[1] as usize >= self.height {
@@ -627,6 +1527,36 @@ impl Tilemap {
 				pos[0] as usize + pos[1] as usize * self.width 
 			) = tile;
 		}
+
+		self.set_timer(pos, 0);
+	}
+
+	/// Gets the timer of a tile, or zero if it's out of bounds.
+	pub fn get_timer(&self, pos: [isize; 2]) -> u8 {
+		debug_assert_eq!(self.timers.len(), self.width * self.height);
+		let pos = self.wrap_pos(pos);
+		if pos[0] < 0 || pos[0] as usize >= self.width 
+			|| pos[1] < 0 || pos[1] as usize >= self.height {
+			return 0;
+		}
+
+		self.timers[pos[0] as usize + pos[1] as usize * self.width]
+	}
+
+	/// Sets the timer of a tile
+	///
+	/// # Panics
+	/// If the tile is out of bounds.
+	pub fn set_timer(&mut self, pos: [isize; 2], timer: u8) {
+		debug_assert_eq!(self.timers.len(), self.width * self.height);
+		let pos = self.wrap_pos(pos);
+
+		if pos[0] < 0 || pos[0] as usize >= self.width 
+			|| pos[1] < 0 || pos[1] as usize >= self.height {
+			panic!("Tried setting a timer out of bounds!");
+		}
+
+		self.timers[pos[0] as usize + pos[1] as usize * self.width] = timer;
 	}
 }
 
@@ -635,6 +1565,8 @@ pub enum AnimationMoveKind {
 	Standard,
 	IceKick,
 	Apply,
+	Fall,
+	Pull,
 }
 
 #[derive(Clone, Copy)]
@@ -653,8 +1585,13 @@ pub enum Animation {
 		to: [isize; 2], 
 		accelerate: bool,
 	},
-	// TODO: Add particles of goop when something is goopified
+	// Something getting pushed, not the thing doing the pushing
+	Push				{ entity_id: u32 },
 	Goopify				{ entity_id: u32, kind: EntityKind },
+	EatCake				{ pos: [isize; 2] },
+	FillHome			{ pos: [isize; 2] },
+	CleanGoop			{ pos: [isize; 2] },
+	SpreadGoop			{ from: [isize; 2], to: [isize; 2] },
 }
 
 #[derive(Clone, Default)]
@@ -677,6 +1614,9 @@ impl Events {
 #[derive(Clone, Copy)]
 pub struct MoveEntity {
 	is_friction_push: bool,
+	is_pull: bool,
+	// How many things are pushing, heavy things need more than one.
+	force: u32,
 	entity_id: u32,
 	from: [isize; 2],
 	direction: Direction,
@@ -686,6 +1626,8 @@ impl MoveEntity {
 	fn new(entity_id: u32, from: [isize; 2], direction: Direction) -> Self {
 		MoveEntity {
 			is_friction_push: false,
+			is_pull: false,
+			force: 1,
 			entity_id,
 			from,
 			direction,
@@ -702,13 +1644,13 @@ impl MoveEntity {
 	}
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FloorKind {
 	Standard,
 	Mossy,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum WallKind {
 	Void,
 	Grass,
@@ -716,7 +1658,7 @@ pub enum WallKind {
 	HappyHome,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Tile {
 	Floor(FloorKind),
 	Wall(WallKind),
@@ -725,8 +1667,21 @@ pub enum Tile {
 	Ice,
 	FloorWithGoop,
 	IceWithGoop,
+	Plate { channel: u8, pressed: bool },
+	Door { channel: u8, open: bool },
+	Conveyor(Direction),
+	Hole,
+	OneWay(Direction),
+	Heater,
+	Water,
+	Exit,
 }
 
+/// How many moves it takes for ice next to a heater to melt into water
+pub const MELT_TURNS: u8 = 3;
+/// How many moves it takes for water to dry up into floor
+pub const DRY_TURNS: u8 = 3;
+
 impl Tile {
 	pub fn graphics(self) -> [Option<TileGraphics>; 3] {
 		use Tile::*;
@@ -734,7 +1689,9 @@ impl Tile {
 
 		// The base tilemap.
 		values[0] = match self {
-			Floor(FloorKind::Standard) | SadHome | Home | FloorWithGoop => 
+			Floor(FloorKind::Standard) | SadHome | Home | FloorWithGoop | 
+			Plate { .. } | Door { .. } | Conveyor(_) | Hole | OneWay(_) | 
+			Heater | Water | Exit => 
 				Some(TileGraphics::Tilemap {
 					atlas: Texture::FloorMap,
 					connects_to_tile: |tile| match tile {
@@ -786,6 +1743,33 @@ impl Tile {
 				Some(TileGraphics::Texture(Texture::HappyHome)),
 			SadHome => Some(TileGraphics::Texture(Texture::SadHome)),
 			Home => Some(TileGraphics::Texture(Texture::Home)),
+			Plate { pressed: false, .. } => 
+				Some(TileGraphics::Texture(Texture::Plate)),
+			Plate { pressed: true, .. } => 
+				Some(TileGraphics::Texture(Texture::PlatePressed)),
+			Door { open: false, .. } => 
+				Some(TileGraphics::Texture(Texture::DoorClosed)),
+			Door { open: true, .. } => 
+				Some(TileGraphics::Texture(Texture::DoorOpen)),
+			Conveyor(Direction::Left) => 
+				Some(TileGraphics::Texture(Texture::ConveyorLeft)),
+			Conveyor(Direction::Right) => 
+				Some(TileGraphics::Texture(Texture::ConveyorRight)),
+			Conveyor(Direction::Up) => 
+				Some(TileGraphics::Texture(Texture::ConveyorUp)),
+			Conveyor(Direction::Down) => 
+				Some(TileGraphics::Texture(Texture::ConveyorDown)),
+			Hole => Some(TileGraphics::Texture(Texture::Hole)),
+			Heater => Some(TileGraphics::Texture(Texture::Heater)),
+			Exit => Some(TileGraphics::Texture(Texture::Exit)),
+			OneWay(Direction::Left) => 
+				Some(TileGraphics::Texture(Texture::OneWayLeft)),
+			OneWay(Direction::Right) => 
+				Some(TileGraphics::Texture(Texture::OneWayRight)),
+			OneWay(Direction::Up) => 
+				Some(TileGraphics::Texture(Texture::OneWayUp)),
+			OneWay(Direction::Down) => 
+				Some(TileGraphics::Texture(Texture::OneWayDown)),
 			_ => None,
 		};
 		
@@ -803,6 +1787,20 @@ impl Tile {
 
 		values
 	}
+
+	/// Graphics for how far along a tile is in melting or drying up.
+	pub fn timer_graphics(self, timer: u8) -> Option<TileGraphics> {
+		match self {
+			Tile::Ice if timer == 0 => None,
+			Tile::Ice if timer + 1 < MELT_TURNS => 
+				Some(TileGraphics::Texture(Texture::IceCracked)),
+			Tile::Ice => Some(TileGraphics::Texture(Texture::IceMelting)),
+			Tile::Water if timer + 1 < DRY_TURNS => 
+				Some(TileGraphics::Texture(Texture::Water)),
+			Tile::Water => Some(TileGraphics::Texture(Texture::Puddle)),
+			_ => None,
+		}
+	}
 }
 
 #[derive(Clone, Copy)]
@@ -843,9 +1841,15 @@ pub enum EntityKind {
 	BucketOfGoop,
 	HumanWithGoop,
 	CakeWithGoop,
+	HeavyCrate,
+	Sponge,
 }
 
 impl EntityKind {
+	pub fn is_human(self) -> bool {
+		matches!(self, EntityKind::Human | EntityKind::HumanWithGoop)
+	}
+
 	pub fn get_texture(&self) -> Texture {
 		match self {
 			EntityKind::Player => Texture::Player,
@@ -854,6 +1858,702 @@ impl EntityKind {
 			EntityKind::BucketOfGoop => Texture::BucketOfGoop,
 			EntityKind::HumanWithGoop => Texture::HumanWithGoop,
 			EntityKind::CakeWithGoop => Texture::CakeWithGoop,
+			EntityKind::HeavyCrate => Texture::HeavyCrate,
+			EntityKind::Sponge => Texture::Sponge,
 		}
 	}
+
+	/// What it looks like while it walks or slides somewhere.
+	pub fn get_moving_texture(&self) -> Texture {
+		match self {
+			EntityKind::Player => Texture::PlayerWalk,
+			_ => self.get_texture(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn play(level: &mut Level, moves: &[Direction]) {
+		let sounds = Sounds::silent();
+		let mut animations = VecDeque::new();
+		for &direction in moves {
+			level.input(direction);
+			while !level.data.active_events.empty() {
+				level.update(&mut animations, &sounds);
+			}
+		}
+	}
+
+	/// Where the first entity of a kind is.
+	fn position_of(level: &Level, kind: EntityKind) -> [isize; 2] {
+		level.data.entities.values().find(|v| v.kind == kind).unwrap().pos
+	}
+
+	#[test]
+	fn levels_file_parses() {
+		let levels = Level::several_from_string(
+			&std::fs::read_to_string("levels.txt").unwrap()
+		).unwrap();
+		assert!(!levels.is_empty());
+	}
+
+	#[test]
+	fn plates_open_doors() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			##H###
+			,.|.:#
+			,.b.,#
+			p.c._#
+			######
+		").unwrap().remove(0);
+
+		assert!(level.tile_is_solid([2, 3]));
+		play(&mut level, &[Right, Right, Right]);
+		assert_eq!(level.data.tiles.get_tile([2, 3]), 
+			Some(Tile::Door { channel: 0, open: true }));
+		play(&mut level, &[Left, Up, Up]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn conveyors_carry_things() {
+		let mut level = Level::several_from_string("
+			pb>>H
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn blocked_conveyors_stop() {
+		let mut level = Level::several_from_string("
+			p.c>#
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right, Direction::Right]);
+		assert!(level.data.active_events.empty());
+		assert_eq!(
+			position_of(&level, EntityKind::Cake),
+			[3, 0],
+		);
+	}
+
+	#[test]
+	fn conveyor_loops_stop() {
+		let mut level = Level::several_from_string("
+			p.c><
+		").unwrap().remove(0);
+
+		// This would never return if the cake kept going around
+		play(&mut level, &[Direction::Right, Direction::Right]);
+		assert!(level.data.active_events.empty());
+		assert_eq!(
+			position_of(&level, EntityKind::Cake),
+			[3, 0],
+		);
+	}
+
+	#[test]
+	fn holes_swallow_humans() {
+		let mut level = Level::several_from_string("
+			pbOH
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(level.data.outcome, 
+			LevelOutcome::Lost(LossReason::FellInHole));
+	}
+
+	#[test]
+	fn sponges_fill_holes() {
+		let mut level = Level::several_from_string("
+			pmO.bH
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::InProgress);
+		assert_eq!(level.data.tiles.get_tile([2, 0]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+		assert!(level.data.entities.values().all(|v| v.kind != EntityKind::Sponge));
+	}
+
+	#[test]
+	fn cake_fills_holes() {
+		let mut level = Level::several_from_string("
+			pcOb.H
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(level.data.tiles.get_tile([2, 0]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+		assert!(level.data.entities.values().all(|v| v.kind != EntityKind::Cake));
+		play(&mut level, &[Direction::Right, Direction::Right, Direction::Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn eating_cake_and_getting_home_are_animated() {
+		let mut level = Level::several_from_string("
+			pcb.H
+		").unwrap().remove(0);
+		level.data.entities.values_mut()
+			.find(|v| v.kind == EntityKind::Human).unwrap()
+			.kind = EntityKind::HumanWithGoop;
+
+		let sounds = Sounds::silent();
+		let mut animations = VecDeque::new();
+		for _ in 0..3 {
+			level.input(Direction::Right);
+			while !level.data.active_events.empty() {
+				level.update(&mut animations, &sounds);
+			}
+		}
+
+		assert!(animations.iter()
+			.any(|v| matches!(v, Animation::EatCake { pos: [2, 0] })));
+		assert!(animations.iter()
+			.any(|v| matches!(v, Animation::FillHome { pos: [4, 0] })));
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn levels_start_without_deadlocks() {
+		let levels = Level::several_from_string(
+			&std::fs::read_to_string("levels.txt").unwrap()
+		).unwrap();
+		for level in levels {
+			assert_eq!(level.find_deadlock(), None);
+		}
+	}
+
+	#[test]
+	fn cornered_humans_are_stuck() {
+		let mut level = Level::several_from_string("
+			#..
+			.bp
+			.H.
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Up, Direction::Down]);
+		assert_eq!(level.data.outcome, LevelOutcome::InProgress);
+		play(&mut level, &[Direction::Left]);
+		assert_eq!(level.data.outcome, 
+			LevelOutcome::Lost(LossReason::HumanStuck));
+	}
+
+	#[test]
+	fn cornered_cake_is_useless() {
+		let mut level = Level::several_from_string("
+			Sp.b.
+			.c...
+			.....
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Down, Direction::Right, Direction::Down]);
+		assert_eq!(level.data.outcome, LevelOutcome::InProgress);
+		play(&mut level, &[Direction::Left]);
+		assert_eq!(level.data.outcome, 
+			LevelOutcome::Lost(LossReason::NotEnoughCake));
+	}
+
+	#[test]
+	fn players_dont_seal_homes() {
+		let level = Level::several_from_string("
+			$ wander
+			#H###
+			#p.b.
+			#####
+		").unwrap().remove(0);
+
+		assert_eq!(level.find_deadlock(), None);
+	}
+
+	#[test]
+	fn patrolling_humans_dont_seal_their_home() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ patrol 1 1 .^
+			#H###
+			#b..p
+			#####
+		").unwrap().remove(0);
+
+		assert_eq!(level.find_deadlock(), None);
+		play(&mut level, &[Left]);
+		assert_eq!(level.data.outcome, LevelOutcome::InProgress);
+		play(&mut level, &[Left]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn cornered_cake_can_still_be_eaten() {
+		// The human wanders, so it can walk into the corner and back out
+		let mut level = Level::several_from_string("
+			$ wander
+			cb.H
+			p...
+		").unwrap().remove(0);
+		level.data.entities.values_mut()
+			.find(|v| v.kind == EntityKind::Human).unwrap()
+			.kind = EntityKind::HumanWithGoop;
+
+		assert_eq!(level.find_deadlock(), None);
+	}
+
+	#[test]
+	fn one_way_tiles() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			pR.L.
+		").unwrap().remove(0);
+
+		// Leaving is fine, even backwards
+		play(&mut level, &[Right, Left]);
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [0, 0]);
+
+		play(&mut level, &[Right, Right, Right]);
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [2, 0]);
+		play(&mut level, &[Left]);
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [2, 0]);
+	}
+
+	#[test]
+	fn one_way_tiles_stop_sliding() {
+		let mut level = Level::several_from_string("
+			pC%%L.
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(position_of(&level, EntityKind::Cake), [3, 0]);
+		assert!(level.data.active_events.empty());
+	}
+
+	#[test]
+	fn pulling() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ pull
+			Cp..
+		").unwrap().remove(0);
+
+		level.toggle_grab();
+		play(&mut level, &[Right, Right]);
+		assert_eq!(position_of(&level, EntityKind::Cake), [2, 0]);
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [3, 0]);
+
+		// Can't pull when the player doesn't move
+		play(&mut level, &[Right]);
+		assert_eq!(position_of(&level, EntityKind::Cake), [2, 0]);
+
+		level.toggle_grab();
+		play(&mut level, &[Left, Right]);
+		assert_eq!(position_of(&level, EntityKind::Cake), [1, 0]);
+	}
+
+	#[test]
+	fn no_pulling_without_metadata() {
+		let mut level = Level::several_from_string("
+			cp..
+		").unwrap().remove(0);
+
+		level.toggle_grab();
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(position_of(&level, EntityKind::Cake), [0, 0]);
+	}
+
+	#[test]
+	fn heavy_crates_need_a_run_up() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			.....
+			.px..
+			.....
+		").unwrap().remove(0);
+
+		play(&mut level, &[Right]);
+		assert_eq!(position_of(&level, EntityKind::HeavyCrate), [2, 1]);
+
+		play(&mut level, &[Left, Right, Right]);
+		assert_eq!(position_of(&level, EntityKind::HeavyCrate), [3, 1]);
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [2, 1]);
+
+		// The run-up carries on while pushing
+		play(&mut level, &[Right]);
+		assert_eq!(position_of(&level, EntityKind::HeavyCrate), [4, 1]);
+	}
+
+	#[test]
+	fn heavy_crates_can_be_pushed_together() {
+		let mut level = Level::several_from_string("
+			pcx.
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(position_of(&level, EntityKind::HeavyCrate), [3, 0]);
+	}
+
+	#[test]
+	fn patrolling_humans() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ patrol 1 0 >.<
+			.b..H
+			p....
+		").unwrap().remove(0);
+
+		play(&mut level, &[Right]);
+		assert_eq!(position_of(&level, EntityKind::Human), [2, 1]);
+		play(&mut level, &[Left]);
+		assert_eq!(position_of(&level, EntityKind::Human), [2, 1]);
+		play(&mut level, &[Right]);
+		assert_eq!(position_of(&level, EntityKind::Human), [1, 1]);
+
+		let mut level = Level::several_from_string("
+			$ patrol 1 0 >
+			.b..H
+			p....
+		").unwrap().remove(0);
+		play(&mut level, &[Right, Left, Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn wandering_is_deterministic() {
+		use Direction::*;
+		let level = |seed: u32| Level::several_from_string(&format!("
+			$ wander {}
+			.......
+			.b...b.
+			...p...
+			.b...b.
+			.......
+		", seed)).unwrap().remove(0);
+
+		let positions = |mut level: Level| {
+			play(&mut level, &[Up, Down, Left, Right, Up, Down]);
+			let mut positions: Vec<_> = level.data.entities.iter()
+				.map(|(&id, v)| (id, v.pos))
+				.collect();
+			positions.sort();
+			positions
+		};
+
+		let first = positions(level(12));
+		assert_eq!(first, positions(level(12)));
+		// The seed has to actually matter
+		assert_ne!(first, positions(level(13)));
+	}
+
+	#[test]
+	fn linked_doors_are_separate() {
+		let level = Level::several_from_string("
+			$ link = I
+			p_|=I
+		").unwrap().remove(0);
+
+		assert_eq!(level.data.tiles.get_tile([2, 0]), 
+			Some(Tile::Door { channel: 0, open: false }));
+		assert_eq!(level.data.tiles.get_tile([4, 0]), 
+			Some(Tile::Door { channel: 1, open: false }));
+		assert!(Level::several_from_string("$ link =\np").is_err());
+	}
+
+	#[test]
+	fn sponges_clean_goop() {
+		let mut level = Level::several_from_string("
+			pmg..
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(level.data.tiles.get_tile([2, 0]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+		assert_eq!(level.data.tiles.get_tile([3, 0]), Some(Tile::FloorWithGoop));
+		assert!(level.data.entities.values().any(|v| v.kind == EntityKind::Sponge));
+	}
+
+	#[test]
+	fn goop_spreads() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ spread 2
+			.....
+			g..b.
+			p...H
+		").unwrap().remove(0);
+
+		play(&mut level, &[Right]);
+		assert_eq!(level.data.tiles.get_tile([1, 1]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+
+		play(&mut level, &[Left]);
+		assert_eq!(level.data.tiles.get_tile([1, 1]), Some(Tile::FloorWithGoop));
+		assert_eq!(level.data.tiles.get_tile([0, 0]), Some(Tile::FloorWithGoop));
+		assert_eq!(level.data.tiles.get_tile([2, 1]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+
+		play(&mut level, &[Right, Left, Right, Left]);
+		let human = level.data.entities.values()
+			.find(|v| v.kind.is_human()).unwrap();
+		assert_eq!(human.kind, EntityKind::HumanWithGoop);
+	}
+
+	#[test]
+	fn sponges_stop_goop_spreading() {
+		let mut level = Level::several_from_string("
+			$ spread 1
+			gm
+			p.
+		").unwrap().remove(0);
+
+		play(&mut level, &[Direction::Right]);
+		assert_eq!(level.data.tiles.get_tile([1, 1]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+		assert_eq!(level.data.tiles.get_tile([0, 0]), Some(Tile::FloorWithGoop));
+	}
+
+	#[test]
+	fn heaters_melt_ice() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			p..%*%%
+		").unwrap().remove(0);
+
+		play(&mut level, &[Right, Left]);
+		assert_eq!(level.data.tiles.get_tile([3, 0]), Some(Tile::Ice));
+		assert_eq!(level.data.tiles.get_timer([3, 0]), 2);
+		// Only ice right next to the heater melts
+		assert_eq!(level.data.tiles.get_timer([6, 0]), 0);
+
+		play(&mut level, &[Right]);
+		assert_eq!(level.data.tiles.get_tile([3, 0]), Some(Tile::Water));
+
+		// Undoing brings the ice back, timer and all
+		level.data = level.undo_stack.pop().unwrap();
+		assert_eq!(level.data.tiles.get_tile([3, 0]), Some(Tile::Ice));
+		assert_eq!(level.data.tiles.get_timer([3, 0]), 2);
+
+		play(&mut level, &[Right, Left, Right, Left]);
+		assert_eq!(level.data.tiles.get_tile([3, 0]), 
+			Some(Tile::Floor(FloorKind::Standard)));
+		assert_eq!(level.data.tiles.get_tile([6, 0]), Some(Tile::Ice));
+	}
+
+	#[test]
+	fn switching_players() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			p...
+			....
+			p...
+		").unwrap().remove(0);
+
+		let top = level.player_ids[0];
+		let bottom = level.player_ids[1];
+		assert_eq!(level.data.entities.get(&top).unwrap().pos, [0, 2]);
+
+		play(&mut level, &[Right]);
+		level.switch_player();
+		play(&mut level, &[Right, Right]);
+		assert_eq!(level.data.entities.get(&top).unwrap().pos, [1, 2]);
+		assert_eq!(level.data.entities.get(&bottom).unwrap().pos, [2, 0]);
+
+		level.switch_player();
+		assert_eq!(level.active_player(), Some(top));
+	}
+
+	#[test]
+	fn players_move_together() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ together
+			p.#.
+			....
+			p...
+		").unwrap().remove(0);
+
+		play(&mut level, &[Right, Right]);
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [1, 2]);
+		assert_eq!(level.data.entities.get(&level.player_ids[1]).unwrap().pos, [2, 0]);
+	}
+
+	#[test]
+	fn coop_players_have_their_own_input() {
+		let sounds = Sounds::silent();
+		let mut animations = VecDeque::new();
+		let mut level = Level::several_from_string("
+			$ coop
+			p...
+			....
+			...p
+		").unwrap().remove(0);
+
+		level.input_coop(&[Some(Direction::Right), Some(Direction::Left)]);
+		assert_eq!(level.data.turn, 1);
+		while !level.data.active_events.empty() {
+			level.update(&mut animations, &sounds);
+		}
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [1, 2]);
+		assert_eq!(level.data.entities.get(&level.player_ids[1]).unwrap().pos, [2, 0]);
+
+		level.input_coop(&[None, Some(Direction::Up)]);
+		while !level.data.active_events.empty() {
+			level.update(&mut animations, &sounds);
+		}
+		assert_eq!(level.data.entities.get(&level.player_ids[0]).unwrap().pos, [1, 2]);
+		assert_eq!(level.data.entities.get(&level.player_ids[1]).unwrap().pos, [2, 1]);
+
+		assert!(Level::several_from_string("$ coop\n$ together\npp").is_err());
+	}
+
+	#[test]
+	fn players_are_in_reading_order() {
+		let level = Level::several_from_string("
+			..p
+			p.p
+		").unwrap().remove(0);
+
+		let positions: Vec<_> = level.player_ids.iter()
+			.map(|id| level.data.entities.get(id).unwrap().pos)
+			.collect();
+		assert_eq!(positions, vec![[2, 1], [0, 0], [2, 0]]);
+	}
+
+	#[test]
+	fn coop_needs_two_players() {
+		assert!(Level::several_from_string("$ coop\np..").is_err());
+		assert!(Level::several_from_string("$ coop\np.p").is_ok());
+		assert!(Level::several_from_string("$ coop\npp.p").is_err());
+	}
+
+	#[test]
+	fn goals() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ goal cake
+			pcS.b
+		").unwrap().remove(0);
+		play(&mut level, &[Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+
+		let mut level = Level::several_from_string("
+			$ goal clean
+			pmg..
+		").unwrap().remove(0);
+		play(&mut level, &[Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::InProgress);
+
+		let mut level = Level::several_from_string("
+			$ goal exit
+			$ goal humans
+			E.pbH
+		").unwrap().remove(0);
+		play(&mut level, &[Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::InProgress);
+		play(&mut level, &[Left, Left, Left]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+
+		assert!(Level::several_from_string("$ goal fun\np").is_err());
+	}
+
+	#[test]
+	fn running_out_of_moves() {
+		use Direction::*;
+		let level = Level::several_from_string("
+			$ goal moves 3
+			p.b.H
+		").unwrap().remove(0);
+
+		let mut slow = level.clone();
+		play(&mut slow, &[Left, Right, Right]);
+		assert_eq!(slow.data.outcome, LevelOutcome::Lost(LossReason::OutOfMoves));
+
+		let mut fast = level;
+		play(&mut fast, &[Right, Right, Right]);
+		assert_eq!(fast.data.outcome, LevelOutcome::Won);
+	}
+
+	#[test]
+	fn challenge_mode() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ par 2
+			.p.bH
+		").unwrap().remove(0);
+		assert_eq!(level.moves_left(), None);
+		assert!(level.start_challenge());
+		assert_eq!(level.moves_left(), Some(2));
+
+		play(&mut level, &[Left]);
+		assert_eq!(level.moves_left(), Some(1));
+
+		// Undoing refunds the move
+		level.data = level.undo_stack.pop().unwrap();
+		assert_eq!(level.moves_left(), Some(2));
+
+		play(&mut level, &[Left, Right]);
+		assert_eq!(level.moves_left(), Some(0));
+		assert_eq!(level.data.outcome, LevelOutcome::Lost(LossReason::OutOfMoves));
+
+		level.data = level.undo_stack.remove(0);
+		play(&mut level, &[Right, Right]);
+		assert_eq!(level.data.outcome, LevelOutcome::Won);
+
+		assert!(!Level::several_from_string("p").unwrap()[0].clone().start_challenge());
+	}
+
+	#[test]
+	fn levels_can_be_beaten_in_par() {
+		use Direction::*;
+		let levels = Level::several_from_string(
+			&std::fs::read_to_string("levels.txt").unwrap()
+		).unwrap();
+		let par_levels: Vec<_> = levels.into_iter().filter(|v| v.par.is_some()).collect();
+
+		// How every level with a par is meant to be beaten, in order
+		let solutions: &[&[Direction]] = &[
+			&[Right, Right, Right, Right, Right],
+			&[Right, Right, Left, Up],
+			&[Left, Left],
+		];
+		assert_eq!(par_levels.len(), solutions.len(), "Every level with a par needs a solution");
+
+		for (mut level, solution) in par_levels.into_iter().zip(solutions) {
+			assert!(level.start_challenge());
+			play(&mut level, solution);
+			assert_eq!(level.data.outcome, LevelOutcome::Won);
+			assert_eq!(Some(level.data.turn), level.par);
+		}
+	}
+
+	#[test]
+	fn wrapping_around() {
+		use Direction::*;
+		let mut level = Level::several_from_string("
+			$ wrap
+			..#.
+			p...
+			#..#
+		").unwrap().remove(0);
+
+		let player = level.player_ids[0];
+		play(&mut level, &[Left]);
+		assert_eq!(level.data.entities.get(&player).unwrap().pos, [3, 1]);
+		play(&mut level, &[Up]);
+		assert_eq!(level.data.entities.get(&player).unwrap().pos, [3, 2]);
+		play(&mut level, &[Up]);
+		assert_eq!(level.data.entities.get(&player).unwrap().pos, [3, 2]);
+
+		// Pushing and sliding work across the edges too
+		let mut level = Level::several_from_string("
+			$ wrap
+			%%.c.p
+		").unwrap().remove(0);
+		play(&mut level, &[Left, Left, Left]);
+		assert_eq!(position_of(&level, EntityKind::Cake), [5, 0]);
+	}
 }
@@ -1,108 +1,190 @@
 use crate::prelude::*;
-use crate::level::{Tile, Animation, AnimationMoveKind, WallKind, TileGraphics};
-use crate::graphics::{TextureVertex, Graphics};
+use crate::level::{Tile, Tilemap, Animation, AnimationMoveKind, WallKind, TileGraphics, EntityKind};
+use crate::graphics::{TextureVertex, ParticleVertex, Graphics};
 use crate::textures::{UVCoords, Texture};
+use crate::camera::Camera;
 use std::collections::{HashMap, VecDeque};
 
+/// How many quads every tile gets in the tilemap buffer. A tile has up to 
+/// four layers, the three from ``Tile::graphics`` and one for its timer, and 
+/// each of them can be a tilemap made out of four quads. The quads a tile 
+/// doesn't use are left empty, so that any tile can be redrawn in place.
+const QUADS_PER_TILE: usize = 4 * 4;
+
 pub struct LevelGraphics {
 	tilemap_change: u32,
 
 	vertices: VertexBuffer<TextureVertex>,
 	indices: IndexBuffer<u32>,
+	// The tiles as they were when the tilemap buffer was last updated, to 
+	// know which tiles have to be redrawn
+	drawn_tiles: Tilemap,
+	// The textures can be reloaded, and then everything has to be redrawn
+	textures_version: u32,
 
 	pub animations: VecDeque<Animation>,
+	pub camera: Camera,
+
+	// How far along the animations were last frame, to know when it's time
+	// to spawn particles. It's negative before the animations have started.
+	animation_time: f32,
 
 	entities: HashMap<u32, EntityGraphics>,
+	entity_quads: QuadBuffer<TextureVertex>,
+
+	particles: Vec<Particle>,
+	particle_quads: QuadBuffer<ParticleVertex>,
+	// Goop droplets and the player marker don't belong to any entity
+	effect_quads: QuadBuffer<TextureVertex>,
+	// Things that glow are drawn again on their own, for bloom
+	glow_quads: QuadBuffer<TextureVertex>,
+	// Where the camera was the last time the level was rendered, so that 
+	// the glow lines up with it
+	camera_matrix: [[f32; 3]; 3],
+	// The particles don't have to look the same every time, so this isn't
+	// part of the level
+	particle_rng: u32,
 }
 
 impl LevelGraphics {
 	pub fn new(graphics: &Graphics, level: &Level) -> LevelGraphics {
 		let (vertices, indices) = generate_level_graphics(graphics, level);
 
-		let mut entities = HashMap::new();
-		for (id, entity) in level.data.entities.iter() {
-			let uv = graphics.textures.get_uv(entity.kind.get_texture());
-			let vertices = VertexBuffer::new(&graphics.display,
-				&[TextureVertex {
-					position: [-0.5, -0.5, 1.0],
-					uv: [uv.left, uv.bottom, uv.texture],
-				},
-				TextureVertex {
-					position: [-0.5, 0.5, 1.0],
-					uv: [uv.left, uv.top, uv.texture],
-				},
-				TextureVertex {
-					position: [0.5, 0.5, 1.0],
-					uv: [uv.right, uv.top, uv.texture],
-				},
-				TextureVertex {
-					position: [0.5, -0.5, 1.0],
-					uv: [uv.right, uv.bottom, uv.texture],
-				}]
-			).unwrap();
-			let indices = IndexBuffer::new(&graphics.display,
-				index::PrimitiveType::TrianglesList,
-				&[0, 1, 2, 0, 2, 3u32],
-			).unwrap();
-
-			entities.insert(*id, EntityGraphics {
-				position: [entity.pos[0] as f32, entity.pos[1] as f32],
-				size: 1.0,
-				vertex_buffer: vertices,
-				index_buffer: indices,
-			});
-		}
-	
 		LevelGraphics {
 			vertices,
 			indices,
-			entities,
+			drawn_tiles: level.data.tiles.clone(),
+			textures_version: graphics.textures_version,
+			entities: generate_entity_graphics(level),
 			animations: VecDeque::new(),
+			camera: Camera::default(),
+			animation_time: -1.0,
 			tilemap_change: level.n_tile_changes,
+			entity_quads: QuadBuffer::new(&graphics.display),
+			particles: Vec::new(),
+			particle_quads: QuadBuffer::new(&graphics.display),
+			effect_quads: QuadBuffer::new(&graphics.display),
+			glow_quads: QuadBuffer::new(&graphics.display),
+			camera_matrix: [[0.0; 3]; 3],
+			particle_rng: 0x9e37_79b9,
 		}
 	}
 
-	pub fn reset(&mut self, graphics: &Graphics, level: &Level) {
-		let (vertices, indices) = generate_level_graphics(graphics, level);
-		self.vertices = vertices;
-		self.indices = indices;
-		self.entities.clear();
-		for (id, entity) in level.data.entities.iter() {
-			let uv = graphics.textures.get_uv(entity.kind.get_texture());
-			let vertices = VertexBuffer::new(&graphics.display,
-				&[TextureVertex {
-					position: [-0.5, -0.5, 1.0],
-					uv: [uv.left, uv.bottom, uv.texture],
-				},
-				TextureVertex {
-					position: [-0.5, 0.5, 1.0],
-					uv: [uv.left, uv.top, uv.texture],
-				},
-				TextureVertex {
-					position: [0.5, 0.5, 1.0],
-					uv: [uv.right, uv.top, uv.texture],
-				},
-				TextureVertex {
-					position: [0.5, -0.5, 1.0],
-					uv: [uv.right, uv.bottom, uv.texture],
-				}]
-			).unwrap();
-			let indices = IndexBuffer::new(&graphics.display,
-				index::PrimitiveType::TrianglesList,
-				&[0, 1, 2, 0, 2, 3u32],
-			).unwrap();
+	/// Removes the old animations before new ones are added.
+	pub fn clear_animations(&mut self) {
+		self.animations.clear();
+		self.animation_time = -1.0;
+	}
 
-			self.entities.insert(*id, EntityGraphics {
-				position: [entity.pos[0] as f32, entity.pos[1] as f32],
-				size: 1.0,
-				vertex_buffer: vertices,
-				index_buffer: indices,
-			});
+	/// Moves the camera and the particles along, and gets rid of the 
+	/// particles that are too old.
+	pub fn update(&mut self, dt: f32) {
+		self.camera.update(dt);
+
+		for particle in self.particles.iter_mut() {
+			particle.age += dt;
+			particle.velocity[1] -= particle.gravity * dt;
+			particle.position[0] += particle.velocity[0] * dt;
+			particle.position[1] += particle.velocity[1] * dt;
 		}
-		self.animations.clear();
+
+		self.particles.retain(|particle| particle.age < particle.lifetime);
+	}
+
+	pub fn reset(&mut self, graphics: &Graphics, level: &Level) {
+		self.update_tilemap(graphics, level);
+		self.entities = generate_entity_graphics(level);
+		self.clear_animations();
+		self.particles.clear();
 		self.tilemap_change = 0;
 	}
 
+	/// Redraws the tiles that changed since the last time, and the ones next 
+	/// to them, since they might connect differently now.
+	fn update_tilemap(&mut self, graphics: &Graphics, level: &Level) {
+		let tiles = &level.data.tiles;
+		if tiles.width != self.drawn_tiles.width || 
+			tiles.height != self.drawn_tiles.height 
+		{
+			let (vertices, indices) = generate_level_graphics(graphics, level);
+			self.vertices = vertices;
+			self.indices = indices;
+			self.drawn_tiles = tiles.clone();
+			return;
+		}
+
+		let changed: Vec<bool> = tiles.buffer.iter().zip(&self.drawn_tiles.buffer)
+			.zip(tiles.timers.iter().zip(&self.drawn_tiles.timers))
+			.map(|((new, old), (new_timer, old_timer))| 
+				new != old || new_timer != old_timer
+			)
+			.collect();
+		if !changed.contains(&true) {
+			return;
+		}
+
+		let is_changed = |pos: [isize; 2]| {
+			let [x, y] = tiles.wrap_pos(pos);
+			x >= 0 && (x as usize) < tiles.width && 
+				y >= 0 && (y as usize) < tiles.height &&
+				changed[x as usize + y as usize * tiles.width]
+		};
+
+		let mut slot_vertices = Vec::with_capacity(QUADS_PER_TILE * 4);
+		let mut slot = 0;
+		for y in -1..=tiles.height as isize {
+			for x in -1..=tiles.width as isize {
+				let needs_redraw = (-1..=1).any(|rel_y| 
+					(-1..=1).any(|rel_x| is_changed([x + rel_x, y + rel_y]))
+				);
+				if needs_redraw {
+					slot_vertices.clear();
+					generate_slot_graphics(graphics, level, x, y, &mut slot_vertices);
+					let start = slot * QUADS_PER_TILE * 4;
+					self.vertices.slice(start..start + QUADS_PER_TILE * 4).unwrap()
+						.write(&slot_vertices);
+				}
+				slot += 1;
+			}
+		}
+
+		self.drawn_tiles = tiles.clone();
+	}
+
+	/// Draws only the things that glow, where they were in the last call to 
+	/// ``render_level``. Right now that's just happy homes.
+	pub fn render_glow(&mut self, graphics: &Graphics, surface: &mut impl Surface, level: &Level) {
+		let uv = graphics.textures.get_animated_uv(Texture::HappyHome, graphics.time);
+		self.glow_quads.clear();
+		for y in 0..level.height() as isize {
+			for x in 0..level.width() as isize {
+				if level.data.tiles.get_tile([x, y]) == Some(Tile::Wall(WallKind::HappyHome)) {
+					graphics.push_texture_quad(
+						&mut self.glow_quads.vertex_data,
+						&mut self.glow_quads.index_data,
+						[x as f32, y as f32, 1.0, 1.0],
+						uv,
+					);
+				}
+			}
+		}
+
+		self.glow_quads.draw(
+			graphics,
+			surface,
+			&graphics.world_texture_program,
+			&uniform! {
+				model_transform: [
+					[1.0, 0.0, 0.0f32],
+					[0.0, 1.0, 0.0f32],
+					[0.0, 0.0, 1.0f32],
+				],
+				camera_transform: self.camera_matrix,
+				atlas: graphics.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
+			},
+		);
+	}
+
 	pub fn render_level(
 		&mut self, 
 		graphics: &Graphics,
@@ -112,28 +194,53 @@ impl LevelGraphics {
 		camera_offset: [f32; 2],
 		time: f32,
 	) {
-		let size = if (level.height() as f32) > (level.width() as f32 / aspect) {
-			1.0 / level.height() as f32
+		let level_size = [level.width() as f32, level.height() as f32];
+
+		// Keep an eye on the players, or the one being controlled
+		let followed_ids: Vec<u32> = if level.together || level.coop {
+			level.players().collect()
 		} else {
-			aspect / level.width() as f32
+			level.active_player().into_iter().collect()
 		};
-		let camera_matrix = [
-			[1.5 * size / aspect, 0.0, 0.0f32],
-			[0.0, 1.5 * size, 0.0f32],
-			[camera_offset[0] * 2.0 * aspect, camera_offset[1] * 2.0, 1.0f32],
-		];
+		let followed: Vec<[f32; 2]> = followed_ids.iter()
+			.filter_map(|id| self.entities.get(id))
+			.map(|gfx| [gfx.position[0] + 0.5, gfx.position[1] + 0.5])
+			.collect();
+		let focus = if followed.is_empty() {
+			[level_size[0] / 2.0, level_size[1] / 2.0]
+		} else {
+			let n = followed.len() as f32;
+			[
+				followed.iter().map(|v| v[0]).sum::<f32>() / n,
+				followed.iter().map(|v| v[1]).sum::<f32>() / n,
+			]
+		};
+		self.camera.look_at(aspect, level_size, graphics.pixel_grid, focus);
 
+		let camera_matrix = self.camera.transform(
+			aspect, 
+			level_size, 
+			graphics.pixel_grid, 
+			camera_offset,
+		);
+		self.camera_matrix = camera_matrix;
 		let model_transform = [
 			[1.0, 0.0, 0.0f32],
 			[0.0, 1.0, 0.0f32],
-			[-(level.width() as f32) / 2.0, -(level.height() as f32) / 2.0, 1.0f32],
+			[0.0, 0.0, 1.0f32],
 		];
 
-		// If the tilemap has changed, change the graphics too!
-		if self.tilemap_change < level.n_tile_changes {
+		if self.textures_version != graphics.textures_version {
 			let (vertices, indices) = generate_level_graphics(graphics, level);
 			self.vertices = vertices;
-			self.indices  = indices;
+			self.indices = indices;
+			self.drawn_tiles = level.data.tiles.clone();
+			self.textures_version = graphics.textures_version;
+		}
+
+		// If the tilemap has changed, change the graphics too!
+		if self.tilemap_change < level.n_tile_changes {
+			self.update_tilemap(graphics, level);
 			self.tilemap_change = level.n_tile_changes;
 		}
 
@@ -159,8 +266,33 @@ impl LevelGraphics {
 			}
 		).unwrap();
 
+		// Goop droplets and the like don't belong to any entity, so they are 
+		// put into their own buffer
+		self.effect_quads.clear();
+		let goop_uv = graphics.textures.get_uv(Texture::GoopDrop);
+
+		// If something happens at 'at' this frame
+		let previous_time = self.animation_time;
+		let happens_now = |at: f32| previous_time < at && at <= time;
+		self.animation_time = time;
+
+		for entity_graphics in self.entities.values_mut() {
+			entity_graphics.moving = false;
+		}
+
 		// Animate stuff
 		for event in self.animations.iter_mut() {
+			// Things look different while they're on their way somewhere
+			if let Animation::Move { entity_id, .. } | 
+				Animation::FailedMove { entity_id, .. } = *event 
+			{
+				if time < 1.0 {
+					if let Some(entity_graphics) = self.entities.get_mut(&entity_id) {
+						entity_graphics.moving = true;
+					}
+				}
+			}
+
 			match *event {
 				Animation::Move {
 					entity_id,
@@ -213,6 +345,42 @@ impl LevelGraphics {
 					entity.position[1] = lerp(from_y as f32, to_y as f32, t);
 					entity.size = 1.0 - t;
 				}
+				Animation::Move {
+					entity_id,
+					from: [from_x, from_y],
+					to: [to_x, to_y],
+					accelerate, decelerate,
+					kind: AnimationMoveKind::Pull,
+				} => {
+					// Drag along a little bit behind whoever is pulling
+					const LAG: f32 = 0.2;
+					let t = smooth_lerp_time(
+						((time - LAG) / (1.0 - LAG)).max(0.0), 
+						accelerate, 
+						decelerate,
+					);
+					let lerp_x = lerp(from_x as f32, to_x as f32, t);
+					let lerp_y = lerp(from_y as f32, to_y as f32, t);
+
+					// @Cleanup: Don't unwrap here, dummy!
+					self.entities.get_mut(&entity_id).unwrap().position 
+						= [lerp_x, lerp_y];
+				}
+				Animation::Move { 
+					entity_id, 
+					from: [from_x, from_y], 
+					to: [to_x, to_y],
+					kind: AnimationMoveKind::Fall,
+					accelerate, ..
+				} => {
+					// Slide into the hole, and shrink faster and faster 
+					// as it falls
+					let t = smooth_lerp_time(time, accelerate, true);
+					let entity = self.entities.get_mut(&entity_id).unwrap();
+					entity.position[0] = lerp(from_x as f32, to_x as f32, t);
+					entity.position[1] = lerp(from_y as f32, to_y as f32, t);
+					entity.size = (1.0 - time * time).max(0.0);
+				}
 
 				Animation::FailedMove {
 					entity_id,
@@ -236,103 +404,440 @@ impl LevelGraphics {
 					// @Cleanup: Don't unwrap here, dummy!
 					self.entities.get_mut(&entity_id).unwrap().position 
 						= [lerp_x, lerp_y];
+
+					// A puff of dust where it bumps into whatever is in the way
+					if happens_now(1.0 / factor) {
+						BUMP_DUST.spawn(
+							&mut self.particles, 
+							&mut self.particle_rng,
+							[
+								(from_x + to_x) as f32 / 2.0 + 0.5, 
+								(from_y + to_y) as f32 / 2.0 + 0.5,
+							],
+						);
+					}
+				}
+				Animation::Push { entity_id } => {
+					if happens_now(0.0) {
+						// Heavy things make more of a thud
+						let heavy = matches!(
+							level.data.entities.get(&entity_id),
+							Some(entity) if entity.kind == EntityKind::HeavyCrate
+						);
+						self.camera.shake(if heavy { HEAVY_PUSH_SHAKE } else { PUSH_SHAKE });
+					}
 				}
 				Animation::Goopify { entity_id, kind } => {
-					let gfx = self.entities.get_mut(&entity_id).unwrap();
-					// @Cleanup: Put the entity graphics creation in a function
-					let uv = graphics.textures.get_uv(kind.get_texture());
-					gfx.vertex_buffer = VertexBuffer::new(&graphics.display,
-						&[TextureVertex {
-							position: [-0.5, -0.5, 1.0],
-							uv: [uv.left, uv.bottom, uv.texture],
-						},
-						TextureVertex {
-							position: [-0.5, 0.5, 1.0],
-							uv: [uv.left, uv.top, uv.texture],
-						},
-						TextureVertex {
-							position: [0.5, 0.5, 1.0],
-							uv: [uv.right, uv.top, uv.texture],
-						},
-						TextureVertex {
-							position: [0.5, -0.5, 1.0],
-							uv: [uv.right, uv.bottom, uv.texture],
-						}]
-					).unwrap();
-					gfx.index_buffer = IndexBuffer::new(&graphics.display,
-						index::PrimitiveType::TrianglesList,
-						&[0, 1, 2, 0, 2, 3u32],
-					).unwrap();
+					let entity = self.entities.get_mut(&entity_id).unwrap();
+					entity.texture = kind.get_texture();
+					entity.moving_texture = kind.get_moving_texture();
+
+					// Eating cake gets rid of the goop, that has crumbs instead
+					if kind != EntityKind::Human && happens_now(0.0) {
+						GOOP_SPLASH.spawn(
+							&mut self.particles, 
+							&mut self.particle_rng,
+							[entity.position[0] + 0.5, entity.position[1] + 0.5],
+						);
+					}
+				}
+				Animation::CleanGoop { pos: [x, y] } => {
+					if happens_now(0.0) {
+						GOOP_SPLASH.spawn(
+							&mut self.particles, 
+							&mut self.particle_rng,
+							[x as f32 + 0.5, y as f32 + 0.5],
+						);
+					}
+				}
+				Animation::EatCake { pos: [x, y] } => {
+					if happens_now(APPLY_TIME) {
+						CAKE_CRUMBS.spawn(
+							&mut self.particles, 
+							&mut self.particle_rng,
+							[x as f32 + 0.5, y as f32 + 0.5],
+						);
+					}
+				}
+				Animation::FillHome { pos: [x, y] } => {
+					if happens_now(APPLY_TIME) {
+						SPARKLES.spawn(
+							&mut self.particles, 
+							&mut self.particle_rng,
+							[x as f32 + 0.5, y as f32 + 0.5],
+						);
+					}
+				}
+				Animation::SpreadGoop { from: [from_x, from_y], to: [to_x, to_y] } => {
+					// A few droplets hopping over to the new tile
+					for i in 0..3 {
+						let t = (time * 1.2 - i as f32 * 0.1).clamp(0.0, 1.0);
+						let drop_x = lerp(from_x as f32, to_x as f32, t) + 0.5;
+						let drop_y = lerp(from_y as f32, to_y as f32, t) + 0.5 
+							+ (t * std::f32::consts::PI).sin() * 0.4;
+						let drop_size = 0.25 * (1.0 - t * t);
+						graphics.push_texture_quad(
+							&mut self.effect_quads.vertex_data,
+							&mut self.effect_quads.index_data,
+							[drop_x - drop_size / 2.0, drop_y - drop_size / 2.0, drop_size, drop_size],
+							goop_uv,
+						);
+					}
 				}
 			}
 		}
 
-		for entity_graphics in self.entities.values() {
-			surface.draw(
-				&entity_graphics.vertex_buffer,
-				&entity_graphics.index_buffer,
-				&graphics.world_texture_program,
-				&uniform! {
-					model_transform: crate::matrix::matrix_mul(model_transform, [
-						[entity_graphics.size, 0.0, 0.0],
-						[0.0, entity_graphics.size, 0.0],
-						[entity_graphics.position[0] + 0.5, entity_graphics.position[1] + 0.5, 1.0],
-					]),
-					camera_transform: camera_matrix,
-					atlas: graphics.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
-				},
-				&DrawParameters {
-					blend: Blend {
-						color: BlendingFunction::Addition {
-							source: LinearBlendingFactor::One,
-							destination: LinearBlendingFactor::OneMinusSourceAlpha,
-						},
-						..Default::default()
-					},
-					..Default::default()
+		// In levels that wrap around, things that go off one edge come in on
+		// the other one, and things close to an edge have a ghost copy on 
+		// the other side, next to the tiles that are copied there.
+		let (width, height) = (level.width() as f32, level.height() as f32);
+		let copy_offsets: &[f32] = if level.data.tiles.wrap {
+			for entity_graphics in self.entities.values_mut() {
+				let [x, y] = &mut entity_graphics.position;
+				*x = (*x + 0.5).rem_euclid(width) - 0.5;
+				*y = (*y + 0.5).rem_euclid(height) - 0.5;
+			}
+
+			&[-1.0, 0.0, 1.0]
+		} else {
+			&[0.0]
+		};
+
+		self.entity_quads.clear();
+		for (&id, entity_graphics) in self.entities.iter() {
+			let texture = if entity_graphics.moving {
+				entity_graphics.moving_texture
+			} else {
+				entity_graphics.texture
+			};
+			// Every entity is a bit out of step, so that animations of
+			// things standing next to each other don't look copy pasted
+			let uv = graphics.textures.get_animated_uv(
+				texture, 
+				graphics.time + id as f32 * 0.37,
+			);
+			let size = entity_graphics.size;
+			for &offset_x in copy_offsets {
+				for &offset_y in copy_offsets {
+					let position = [
+						entity_graphics.position[0] + offset_x * width,
+						entity_graphics.position[1] + offset_y * height,
+					];
+					if position[0] <= -2.0 || position[0] >= width + 1.0 ||
+						position[1] <= -2.0 || position[1] >= height + 1.0
+					{
+						continue;
+					}
+
+					// Entities are scaled around their middle
+					graphics.push_texture_quad(
+						&mut self.entity_quads.vertex_data,
+						&mut self.entity_quads.index_data,
+						[
+							position[0] + 0.5 - size / 2.0, 
+							position[1] + 0.5 - size / 2.0, 
+							size, 
+							size,
+						],
+						uv,
+					);
 				}
+			}
+		}
+
+		self.entity_quads.draw(
+			graphics,
+			surface,
+			&graphics.world_texture_program,
+			&uniform! {
+				model_transform: model_transform,
+				camera_transform: camera_matrix,
+				atlas: graphics.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
+			},
+		);
+
+		self.particle_quads.clear();
+		for particle in self.particles.iter() {
+			let t = particle.age / particle.lifetime;
+			let size = particle.size;
+			graphics.push_particle_quad(
+				&mut self.particle_quads.vertex_data,
+				&mut self.particle_quads.index_data,
+				[
+					particle.position[0] - size / 2.0, 
+					particle.position[1] - size / 2.0, 
+					size, 
+					size,
+				],
+				graphics.textures.get_animated_uv(particle.texture, graphics.time),
+				1.0 - t * t,
+			);
+		}
+
+		self.particle_quads.draw(
+			graphics,
+			surface,
+			&graphics.particle_program,
+			&uniform! {
+				model_transform: model_transform,
+				camera_transform: camera_matrix,
+				atlas: graphics.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
+			},
+		);
+
+		// Show which player is being controlled when there is a choice
+		if level.has_several_players() && !level.together && !level.coop {
+			let entities = &self.entities;
+			if let Some(gfx) = level.active_player().and_then(|id| entities.get(&id)) {
+				graphics.push_texture_quad(
+					&mut self.effect_quads.vertex_data,
+					&mut self.effect_quads.index_data,
+					[gfx.position[0] + 0.25, gfx.position[1] + 0.8, 0.5, 0.5],
+					graphics.textures.get_animated_uv(Texture::PlayerMarker, graphics.time),
+				);
+			}
+		}
+
+		self.effect_quads.draw(
+			graphics,
+			surface,
+			&graphics.world_texture_program,
+			&uniform! {
+				model_transform: model_transform,
+				camera_transform: camera_matrix,
+				atlas: graphics.textures.atlas.sampled().magnify_filter(uniforms::MagnifySamplerFilter::Nearest),
+			},
+		);
+	}
+}
+
+const PUSH_SHAKE: f32 = 0.3;
+const HEAVY_PUSH_SHAKE: f32 = 0.7;
+
+/// How far into an ``Apply`` animation the thing has disappeared into the 
+/// tile.
+const APPLY_TIME: f32 = 0.7;
+
+const GOOP_SPLASH: ParticleBurst = ParticleBurst {
+	texture: Texture::GoopDrop,
+	n_particles: 8,
+	speed: 2.0,
+	lift: 2.0,
+	gravity: 10.0,
+	size: 0.25,
+	lifetime: 0.6,
+};
+
+const CAKE_CRUMBS: ParticleBurst = ParticleBurst {
+	texture: Texture::Crumb,
+	n_particles: 6,
+	speed: 1.5,
+	lift: 1.5,
+	gravity: 8.0,
+	size: 0.2,
+	lifetime: 0.5,
+};
+
+const SPARKLES: ParticleBurst = ParticleBurst {
+	texture: Texture::Sparkle,
+	n_particles: 10,
+	speed: 1.2,
+	lift: 0.5,
+	gravity: -1.0,
+	size: 0.35,
+	lifetime: 1.0,
+};
+
+const BUMP_DUST: ParticleBurst = ParticleBurst {
+	texture: Texture::Dust,
+	n_particles: 4,
+	speed: 0.8,
+	lift: 0.3,
+	gravity: 0.0,
+	size: 0.2,
+	lifetime: 0.35,
+};
+
+struct Particle {
+	position: [f32; 2],
+	velocity: [f32; 2],
+	// How much it's pulled downwards, negative values make it float up
+	gravity: f32,
+	size: f32,
+	// In seconds
+	age: f32,
+	lifetime: f32,
+	texture: Texture,
+}
+
+/// A bunch of particles flying out from one spot.
+struct ParticleBurst {
+	texture: Texture,
+	n_particles: usize,
+	speed: f32,
+	// Extra upwards speed, so that it looks like a splash
+	lift: f32,
+	gravity: f32,
+	size: f32,
+	lifetime: f32,
+}
+
+impl ParticleBurst {
+	fn spawn(&self, particles: &mut Vec<Particle>, rng: &mut u32, pos: [f32; 2]) {
+		for i in 0..self.n_particles {
+			// Spread out evenly, with a bit of randomness so that it 
+			// doesn't look too neat
+			let angle = (i as f32 + random_f32(rng) * 0.8) / self.n_particles as f32 
+				* std::f32::consts::TAU;
+			let speed = self.speed * lerp(0.5, 1.0, random_f32(rng));
+			particles.push(Particle {
+				position: pos,
+				velocity: [angle.cos() * speed, angle.sin() * speed + self.lift],
+				gravity: self.gravity,
+				size: self.size * lerp(0.7, 1.0, random_f32(rng)),
+				age: 0.0,
+				lifetime: self.lifetime * lerp(0.7, 1.0, random_f32(rng)),
+				texture: self.texture,
+			});
+		}
+	}
+}
+
+/// Xorshift, gives a number from 0 to 1.
+fn random_f32(state: &mut u32) -> f32 {
+	let mut x = *state;
+	x ^= x << 13;
+	x ^= x >> 17;
+	x ^= x << 5;
+	*state = x;
+	x as f32 / u32::MAX as f32
+}
+
+/// A vertex buffer and an index buffer for quads that are rewritten every 
+/// frame. They only grow, so that they don't have to be reallocated all the
+/// time.
+struct QuadBuffer<V: Vertex> {
+	vertices: VertexBuffer<V>,
+	indices: IndexBuffer<u32>,
+	// Kept around to not allocate new ones every frame
+	vertex_data: Vec<V>,
+	index_data: Vec<u32>,
+}
+
+impl<V: Vertex> QuadBuffer<V> {
+	fn new(display: &Display) -> Self {
+		QuadBuffer {
+			vertices: VertexBuffer::empty_dynamic(display, 0).unwrap(),
+			indices: IndexBuffer::empty_dynamic(
+				display, 
+				index::PrimitiveType::TrianglesList, 
+				0,
+			).unwrap(),
+			vertex_data: Vec::new(),
+			index_data: Vec::new(),
+		}
+	}
+
+	fn clear(&mut self) {
+		self.vertex_data.clear();
+		self.index_data.clear();
+	}
+
+	/// Draws all the quads that were pushed since the last ``clear``.
+	fn draw(
+		&mut self, 
+		graphics: &Graphics, 
+		surface: &mut impl Surface, 
+		program: &Program, 
+		uniforms: &impl uniforms::Uniforms,
+	) {
+		if self.vertex_data.is_empty() {
+			return;
+		}
+
+		if self.vertex_data.len() > self.vertices.len() {
+			let capacity = self.vertex_data.len().next_power_of_two();
+			self.vertices = 
+				VertexBuffer::empty_dynamic(&graphics.display, capacity).unwrap();
+			self.indices = IndexBuffer::empty_dynamic(
+				&graphics.display, 
+				index::PrimitiveType::TrianglesList, 
+				capacity / 4 * 6,
 			).unwrap();
 		}
+
+		let n_vertices = self.vertex_data.len();
+		let n_indices = self.index_data.len();
+		self.vertices.slice(0..n_vertices).unwrap().write(&self.vertex_data);
+		self.indices.slice(0..n_indices).unwrap().write(&self.index_data);
+
+		surface.draw(
+			self.vertices.slice(0..n_vertices).unwrap(),
+			self.indices.slice(0..n_indices).unwrap(),
+			program,
+			uniforms,
+			&DrawParameters {
+				blend: Blend {
+					color: BlendingFunction::Addition {
+						source: LinearBlendingFactor::One,
+						destination: LinearBlendingFactor::OneMinusSourceAlpha,
+					},
+					..Default::default()
+				},
+				..Default::default()
+			}
+		).unwrap();
 	}
 }
 
 struct EntityGraphics {
 	position: [f32; 2],
 	size: f32,
-	vertex_buffer: VertexBuffer<TextureVertex>,
-	index_buffer: IndexBuffer<u32>,
+	texture: Texture,
+	moving_texture: Texture,
+	moving: bool,
+}
+
+fn generate_entity_graphics(level: &Level) -> HashMap<u32, EntityGraphics> {
+	level.data.entities.iter()
+		.map(|(&id, entity)| (id, EntityGraphics {
+			position: [entity.pos[0] as f32, entity.pos[1] as f32],
+			size: 1.0,
+			texture: level.get_entity_texture(id, false),
+			moving_texture: level.get_entity_texture(id, true),
+			moving: false,
+		}))
+		.collect()
 }
 
 fn generate_level_graphics(
 	graphics: &Graphics,
 	level: &Level, 
 ) -> (VertexBuffer<TextureVertex>, IndexBuffer<u32>) {
-	let mut vertices = Vec::new();
-	let mut indices = Vec::new();
+	let n_slots = (level.width() + 2) * (level.height() + 2);
+	let mut vertices = Vec::with_capacity(n_slots * QUADS_PER_TILE * 4);
 
 	for y in -1..=level.height() as isize {
 		for x in -1..=level.width() as isize {
-			let tile = level.data.tiles.get_tile([x, y])
-				.unwrap_or(Tile::Wall(WallKind::Void));
-			let tile_graphics = tile.graphics();
-
-			for graphic in (&tile_graphics).iter().copied().filter_map(|v| v) {
-				generate_tile_graphics(
-					graphics,
-					level,
-					x,
-					y,
-					[x as f32, y as f32, 1.0, 1.0],
-					graphic,
-					&mut vertices,
-					&mut indices,
-				);
-			}
+			generate_slot_graphics(graphics, level, x, y, &mut vertices);
 		}
 	}
 
+	// Every tile has the same number of quads, so the indices never change
+	let mut indices = Vec::with_capacity(n_slots * QUADS_PER_TILE * 6);
+	for quad in 0..(n_slots * QUADS_PER_TILE) as u32 {
+		let vert_index = quad * 4;
+		indices.push(vert_index);
+		indices.push(vert_index + 1);
+		indices.push(vert_index + 2);
+
+		indices.push(vert_index);
+		indices.push(vert_index + 2);
+		indices.push(vert_index + 3);
+	}
+
 	(
-		VertexBuffer::new(&graphics.display, &vertices).unwrap(),
+		VertexBuffer::dynamic(&graphics.display, &vertices).unwrap(),
 		IndexBuffer::new(
 			&graphics.display, 
 			glium::index::PrimitiveType::TrianglesList, 
@@ -341,6 +846,45 @@ fn generate_level_graphics(
 	)
 }
 
+/// Generates exactly ``QUADS_PER_TILE`` quads for the tile at x, y.
+fn generate_slot_graphics(
+	graphics: &Graphics,
+	level: &Level,
+	x: isize,
+	y: isize,
+	vertices: &mut Vec<TextureVertex>,
+) {
+	let start = vertices.len();
+	// The index buffer is the same for every tile, so these are thrown away
+	let mut indices = Vec::new();
+
+	let tile = level.data.tiles.get_tile([x, y])
+		.unwrap_or(Tile::Wall(WallKind::Void));
+	let tile_graphics = tile.graphics();
+	let timer_graphics = tile.timer_graphics(level.data.tiles.get_timer([x, y]));
+
+	for graphic in tile_graphics.iter().flatten().copied()
+		.chain(timer_graphics)
+	{
+		generate_tile_graphics(
+			graphics,
+			level,
+			x,
+			y,
+			[x as f32, y as f32, 1.0, 1.0],
+			graphic,
+			vertices,
+			&mut indices,
+		);
+	}
+
+	debug_assert!(vertices.len() - start <= QUADS_PER_TILE * 4);
+	vertices.resize(start + QUADS_PER_TILE * 4, TextureVertex {
+		position: [0.0; 3],
+		uv: [0.0; 3],
+	});
+}
+
 /// A function where 0 <= t <= 2, 0 and 2 will give 0.
 #[inline]
 fn double_lerp(t: f32, accelerate: bool) -> f32 {
@@ -385,14 +929,24 @@ fn generate_tile_graphics(
 						.unwrap_or(false);
 				}
 			}
-			generate_tilemap_tile_graphics(
-				graphics,
-				pos,
-				atlas,
-				data,
-				vertices,
-				indices,
-			);
+			if graphics.textures.is_blob(atlas) {
+				// Blob tilemaps have a whole tile for every case
+				graphics.push_texture_quad(
+					vertices,
+					indices,
+					pos,
+					graphics.textures.get_blob_uv(atlas, data),
+				);
+			} else {
+				generate_tilemap_tile_graphics(
+					graphics,
+					pos,
+					atlas,
+					data,
+					vertices,
+					indices,
+				);
+			}
 		},
 		TileGraphics::Texture(texture) => {
 			let uv = graphics.textures.get_uv(texture);
@@ -410,7 +964,7 @@ fn generate_tile_graphics(
 /// info, and generate a valid thing!
 ///
 /// The surrounding info is a map like this:
-/// 6 7 9
+/// 6 7 8
 /// 3 4 5
 /// 0 1 2
 /// where 4 is the tile being rendered(ignored),
@@ -1,10 +1,18 @@
+// implement_vertex! checks for an old clippy cfg that rustc doesn't know about
+#![allow(unexpected_cfgs)]
+
+#[macro_use]
+mod shaders;
 mod game_state;
 mod level;
 mod graphics;
 mod textures;
 mod level_graphics;
 mod matrix;
+mod camera;
 mod sounds;
+mod win_condition;
+mod post_processing;
 
 mod prelude {
 	pub use glium::*;
@@ -31,37 +39,75 @@ pub enum Direction {
 	Down,
 }
 
+impl Direction {
+	pub fn opposite(self) -> Direction {
+		match self {
+			Direction::Left => Direction::Right,
+			Direction::Right => Direction::Left,
+			Direction::Up => Direction::Down,
+			Direction::Down => Direction::Up,
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Input {
-	Move(Direction),
+	// The first number is which set of keys it came from, which in co-op
+	// levels is which player to move.
+	Move(usize, Direction),
+	Grab,
+	SwitchPlayer,
 	Confirm,
 	Undo,
 	NextLevel,
 	PrevLevel,
 	Randomize,
+	ToggleChallenge,
+	ToggleFrameTime,
+	ZoomIn,
+	ZoomOut,
+	TogglePixelPerfect,
 }
 
+pub const WINDOW_TITLE: &str = "| Xxx_SokobaN_xxX |";
+
+/// When drawing pixel perfect, the textures are scaled up by the biggest 
+/// whole number that still leaves at least this many of their pixels on 
+/// the screen vertically.
+const MIN_PIXELS_HIGH: u32 = 240;
+
 fn main() {
 	let mut aspect = 1024.0 / 768.0;
     let events_loop = glium::glutin::event_loop::EventLoop::new();
     let wb = glium::glutin::window::WindowBuilder::new()
         .with_inner_size(glium::glutin::dpi::LogicalSize::new(1024.0, 768.0))
-        .with_title("| Xxx_SokobaN_xxX |");
+        .with_title(WINDOW_TITLE);
     let cb = glium::glutin::ContextBuilder::new();
     let display = glium::Display::new(wb, cb, &events_loop).unwrap();
 
 	let sounds = sounds::Sounds::load().unwrap();
 
 	let mut keybindings = HashMap::new();
-	keybindings.insert(72, Input::Move(Direction::Up));
-	keybindings.insert(75, Input::Move(Direction::Left));
-	keybindings.insert(80, Input::Move(Direction::Down));
-	keybindings.insert(77, Input::Move(Direction::Right));
+	keybindings.insert(72, Input::Move(0, Direction::Up));
+	keybindings.insert(75, Input::Move(0, Direction::Left));
+	keybindings.insert(80, Input::Move(0, Direction::Down));
+	keybindings.insert(77, Input::Move(0, Direction::Right));
+	keybindings.insert(17, Input::Move(1, Direction::Up));
+	keybindings.insert(30, Input::Move(1, Direction::Left));
+	keybindings.insert(31, Input::Move(1, Direction::Down));
+	keybindings.insert(32, Input::Move(1, Direction::Right));
 	keybindings.insert(57, Input::Confirm);
 	keybindings.insert(44, Input::Undo);
+	keybindings.insert(34, Input::Grab);
+	keybindings.insert(15, Input::SwitchPlayer);
 	keybindings.insert(62, Input::PrevLevel);
 	keybindings.insert(63, Input::NextLevel);
 	keybindings.insert(59, Input::Randomize);
+	keybindings.insert(46, Input::ToggleChallenge);
+	keybindings.insert(61, Input::ToggleFrameTime);
+	keybindings.insert(13, Input::ZoomIn);
+	keybindings.insert(12, Input::ZoomOut);
+	keybindings.insert(60, Input::TogglePixelPerfect);
 
 	let mut graphics = graphics::Graphics::new(&display, sounds);
 
@@ -72,6 +118,17 @@ fn main() {
 		).unwrap()
 	);
 
+	// How long rendering a frame takes on average, in microseconds
+	let mut frame_time = 0.0;
+	let mut show_frame_time = false;
+
+	// Pixel perfect mode draws everything to this first, and then scales it
+	// up to fit the window
+	let mut pixel_perfect = false;
+	let mut pixel_buffer: Option<texture::Texture2d> = None;
+
+	let mut post_processing = post_processing::PostProcessing::new(&display);
+
 	let mut previous_frame = Instant::now();
 	events_loop.run(move |event, _, control_flow| {
 		let current_frame = Instant::now();
@@ -108,7 +165,13 @@ fn main() {
 
 				if let Some(&keybind) = keybindings.get(&scancode) {
 					if key_state == ElementState::Pressed {
-						state.input(&mut graphics, keybind).unwrap();
+						if keybind == Input::ToggleFrameTime {
+							show_frame_time = !show_frame_time;
+						} else if keybind == Input::TogglePixelPerfect {
+							pixel_perfect = !pixel_perfect;
+						} else {
+							state.input(&mut graphics, keybind).unwrap();
+						}
 					}
 				}else {
 					println!("Unknown key scancode: '{}'", scancode);
@@ -121,8 +184,69 @@ fn main() {
 			// println!("Unknown event; {:?}", unknown_event),
 		}
 
+		graphics.hot_load_assets(dt);
+
 		let mut frame = display.draw();
-		state.render(&mut frame, &mut graphics, aspect, dt).unwrap();
+		if pixel_perfect {
+			let (window_width, window_height) = frame.get_dimensions();
+			let scale = (window_height / MIN_PIXELS_HIGH).max(1);
+			let (width, height) = (window_width / scale, window_height / scale);
+
+			let buffer_size = pixel_buffer.as_ref()
+				.map(|buffer| (buffer.width(), buffer.height()));
+			if buffer_size != Some((width, height)) {
+				pixel_buffer = Some(
+					texture::Texture2d::empty(&display, width, height).unwrap()
+				);
+			}
+			let buffer = pixel_buffer.as_ref().unwrap();
+
+			graphics.pixel_grid = Some(graphics::PixelGrid {
+				width,
+				height,
+				tile_pixels: graphics.textures.texture_size[1],
+			});
+			let mut buffer_surface = buffer.as_surface();
+			buffer_surface.clear_color(0.0, 0.0, 0.0, 1.0);
+			post_processing.render(
+				&mut buffer_surface, 
+				&mut state,
+				&mut graphics, 
+				width as f32 / height as f32, 
+				dt,
+			).unwrap();
+			graphics.pixel_grid = None;
+
+			// Whatever doesn't fit a whole number of times is left black 
+			// around the edges
+			frame.clear_color(0.0, 0.0, 0.0, 1.0);
+			buffer_surface.blit_color(
+				&Rect { left: 0, bottom: 0, width, height },
+				&frame,
+				&BlitTarget {
+					left: (window_width - width * scale) / 2,
+					bottom: (window_height - height * scale) / 2,
+					width: (width * scale) as i32,
+					height: (height * scale) as i32,
+				},
+				uniforms::MagnifySamplerFilter::Nearest,
+			);
+		} else {
+			post_processing.render(&mut frame, &mut state, &mut graphics, aspect, dt).unwrap();
+		}
+
+		graphics.draw_asset_error(&mut frame, aspect);
+
+		// Smooth it out a bit, otherwise it's impossible to read
+		let render_time = current_frame.elapsed().as_micros() as f32;
+		frame_time = lerp(frame_time, render_time, 0.05);
+		if show_frame_time {
+			const SIZE: f32 = 0.08;
+			let micros = frame_time as u32;
+			let width = graphics::Graphics::number_width(micros, SIZE);
+			graphics.draw_number(&mut frame, aspect, [aspect - 0.05 - width, 0.95], SIZE, micros);
+		}
+
 		frame.finish().unwrap();
 	});
 }
@@ -0,0 +1,271 @@
+use crate::prelude::*;
+use crate::game_state::GameState;
+use crate::shaders::Shader;
+use texture::Texture2d;
+use uniforms::{Sampler, SamplerWrapFunction, MagnifySamplerFilter, MinifySamplerFilter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EffectKind {
+	/// Darkens the corners of the screen.
+	Vignette,
+	/// Dark lines and a bit of color bleeding, like an old tv.
+	Scanlines,
+	/// Only a few shades of every color, the strength is how many.
+	Palette,
+	/// Makes happy homes glow.
+	Bloom,
+}
+
+impl EffectKind {
+	fn from_name(name: &str) -> Option<EffectKind> {
+		match name {
+			"vignette" => Some(EffectKind::Vignette),
+			"scanlines" => Some(EffectKind::Scanlines),
+			"palette" => Some(EffectKind::Palette),
+			"bloom" => Some(EffectKind::Bloom),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Effect {
+	pub kind: EffectKind,
+	pub strength: f32,
+}
+
+/// Reads the effects out of a settings file. Every effect is on a line of
+/// its own, ``effect ; <kind> ; <strength>``, and they are applied from the
+/// top down.
+pub fn parse_settings(contents: &str) -> Result<Vec<Effect>, String> {
+	let mut effects = Vec::new();
+	for (line_num, line) in contents.lines()
+		.enumerate()
+		.map(|(i, v)| (i, v.trim()))
+		.filter(|(_, v)| !v.is_empty() && !v.starts_with("//"))
+	{
+		let parts: Vec<_> = line.split(';').map(|v| v.trim()).collect();
+		match *parts.as_slice() {
+			["effect", kind, strength] => {
+				let kind = EffectKind::from_name(kind).ok_or_else(||
+					format!("Line {}: Unknown effect '{}'", line_num + 1, kind)
+				)?;
+				let strength = strength.parse().map_err(|_|
+					format!("Line {}: '{}' isn't a number", line_num + 1, strength)
+				)?;
+				effects.push(Effect { kind, strength });
+			}
+			[name, ..] => return Err(format!(
+				"Line {}: Expected 'effect ; <kind> ; <strength>', got '{}'",
+				line_num + 1,
+				name,
+			)),
+			[] => unreachable!("Empty lines are skipped"),
+		}
+	}
+	Ok(effects)
+}
+
+/// The shaders for every kind of effect. They all share the same vertex
+/// shader, which just covers the screen.
+pub struct EffectPrograms {
+	vignette: Shader,
+	scanlines: Shader,
+	palette: Shader,
+	bloom: Shader,
+}
+
+impl EffectPrograms {
+	pub fn load(display: &Display) -> EffectPrograms {
+		EffectPrograms {
+			vignette: load_shader!(display, "post", "vignette"),
+			scanlines: load_shader!(display, "post", "scanlines"),
+			palette: load_shader!(display, "post", "palette"),
+			bloom: load_shader!(display, "post", "bloom"),
+		}
+	}
+
+	pub fn get(&self, kind: EffectKind) -> &Shader {
+		match kind {
+			EffectKind::Vignette => &self.vignette,
+			EffectKind::Scanlines => &self.scanlines,
+			EffectKind::Palette => &self.palette,
+			EffectKind::Bloom => &self.bloom,
+		}
+	}
+
+	fn all_mut(&mut self) -> [&mut Shader; 4] {
+		[&mut self.vignette, &mut self.scanlines, &mut self.palette, &mut self.bloom]
+	}
+
+	/// Returns true if any of them were compiled again.
+	pub fn hot_load(&mut self, display: &Display) -> bool {
+		let mut changed = false;
+		for shader in self.all_mut().iter_mut() {
+			changed |= shader.hot_load(display);
+		}
+		changed
+	}
+
+	pub fn error(&self) -> Option<String> {
+		[&self.vignette, &self.scanlines, &self.palette, &self.bloom]
+			.iter()
+			.find_map(|shader| shader.error.clone())
+	}
+}
+
+#[derive(Clone, Copy)]
+struct ScreenVertex {
+	position: [f32; 2],
+}
+
+implement_vertex!(ScreenVertex, position);
+
+/// The textures the game is drawn to before the effects are put on top.
+struct Buffers {
+	scene: Texture2d,
+	glow: Texture2d,
+	// Every effect draws to the one the previous effect didn't, except the
+	// last one, which draws to the screen
+	steps: [Texture2d; 2],
+}
+
+impl Buffers {
+	fn new(display: &Display, width: u32, height: u32) -> Buffers {
+		let empty = || Texture2d::empty(display, width, height).unwrap();
+		Buffers {
+			scene: empty(),
+			glow: empty(),
+			steps: [empty(), empty()],
+		}
+	}
+}
+
+pub struct PostProcessing {
+	vertices: VertexBuffer<ScreenVertex>,
+	indices: IndexBuffer<u32>,
+	// Only there while there are effects, since they take up a few screens
+	// worth of memory
+	buffers: Option<Buffers>,
+}
+
+impl PostProcessing {
+	pub fn new(display: &Display) -> PostProcessing {
+		let vertices = VertexBuffer::new(display, &[
+			ScreenVertex { position: [-1.0, -1.0] },
+			ScreenVertex { position: [-1.0, 1.0] },
+			ScreenVertex { position: [1.0, 1.0] },
+			ScreenVertex { position: [1.0, -1.0] },
+		]).unwrap();
+		let indices = IndexBuffer::new(display,
+			index::PrimitiveType::TrianglesList,
+			&[0, 1, 2, 0, 2, 3u32],
+		).unwrap();
+
+		PostProcessing {
+			vertices,
+			indices,
+			buffers: None,
+		}
+	}
+
+	/// Draws the game onto ``target`` with all the effects in the settings.
+	/// Without any effects, it's drawn straight onto it.
+	pub fn render(
+		&mut self,
+		target: &mut impl Surface,
+		state: &mut GameState,
+		graphics: &mut Graphics,
+		aspect: f32,
+		dt: f32,
+	) -> Result<(), String> {
+		if graphics.effects.is_empty() {
+			self.buffers = None;
+			return state.render(target, graphics, aspect, dt);
+		}
+
+		let (width, height) = target.get_dimensions();
+		let buffer_size = self.buffers.as_ref()
+			.map(|buffers| (buffers.scene.width(), buffers.scene.height()));
+		if buffer_size != Some((width, height)) {
+			self.buffers = Some(Buffers::new(&graphics.display, width, height));
+		}
+		let buffers = self.buffers.as_ref().unwrap();
+
+		let mut scene = buffers.scene.as_surface();
+		scene.clear_color(0.0, 0.0, 0.0, 1.0);
+		state.render(&mut scene, graphics, aspect, dt)?;
+
+		let mut glow = buffers.glow.as_surface();
+		glow.clear_color(0.0, 0.0, 0.0, 0.0);
+		if graphics.effects.iter().any(|v| v.kind == EffectKind::Bloom) {
+			state.render_glow(&mut glow, graphics);
+		}
+
+		let mut input = &buffers.scene;
+		for (i, effect) in graphics.effects.iter().enumerate() {
+			let uniforms = uniform! {
+				scene: sample(input),
+				glow: sample(&buffers.glow),
+				resolution: [width as f32, height as f32],
+				strength: effect.strength,
+				time: graphics.time,
+			};
+			let program = graphics.effect_programs.get(effect.kind);
+
+			if i + 1 == graphics.effects.len() {
+				target.draw(
+					&self.vertices,
+					&self.indices,
+					program,
+					&uniforms,
+					&Default::default(),
+				).unwrap();
+			} else {
+				let output = &buffers.steps[i % 2];
+				output.as_surface().draw(
+					&self.vertices,
+					&self.indices,
+					program,
+					&uniforms,
+					&Default::default(),
+				).unwrap();
+				input = output;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// The buffers are exactly as big as the screen, so every pixel of them
+/// should end up on one pixel of the screen.
+fn sample(texture: &Texture2d) -> Sampler<'_, Texture2d> {
+	texture.sampled()
+		.wrap_function(SamplerWrapFunction::Clamp)
+		.minify_filter(MinifySamplerFilter::Nearest)
+		.magnify_filter(MagnifySamplerFilter::Nearest)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn settings_are_parsed_in_order() {
+		let effects = parse_settings("
+			// Comments and empty lines are skipped
+
+			effect ; palette ; 8
+			effect	; bloom	; 0.5
+		").unwrap();
+		assert_eq!(effects, vec![
+			Effect { kind: EffectKind::Palette, strength: 8.0 },
+			Effect { kind: EffectKind::Bloom, strength: 0.5 },
+		]);
+
+		assert!(parse_settings("effect ; sepia ; 1").is_err());
+		assert!(parse_settings("effect ; vignette").is_err());
+		assert!(parse_settings("effect ; vignette ; lots").is_err());
+	}
+}
@@ -0,0 +1,109 @@
+use crate::prelude::*;
+use crate::graphics::newest_modification;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::ops::Deref;
+use std::fs;
+
+const SHADER_DIRECTORY: &str = "assets/shaders";
+
+/// Loads the shader called ``$name`` from the shader directory, or one 
+/// made from the ``$vertex`` and ``$fragment`` shaders. The version of it 
+/// that was there when the game was built is kept around, in case the files 
+/// are broken when the game starts.
+macro_rules! load_shader {
+	($display:expr, $name:literal) => {
+		load_shader!($display, $name, $name)
+	};
+	($display:expr, $vertex:literal, $fragment:literal) => {
+		$crate::shaders::Shader::load(
+			$display,
+			[$vertex, $fragment],
+			include_str!(concat!("../assets/shaders/", $vertex, ".vert")),
+			include_str!(concat!("../assets/shaders/", $fragment, ".frag")),
+		)
+	};
+}
+
+/// A program made from ``<vertex>.vert`` and ``<fragment>.frag`` in the 
+/// shader directory, that is compiled again whenever either of them 
+/// changes. If they don't compile, the program it had before is kept.
+pub struct Shader {
+	// The vertex and the fragment shader
+	names: [&'static str; 2],
+	program: Program,
+	modified: Option<SystemTime>,
+	// Why the files couldn't be compiled the last time, if they couldn't
+	pub error: Option<String>,
+}
+
+impl Shader {
+	/// Use ``load_shader!`` instead, it finds the built in version for you.
+	pub fn load(display: &Display, names: [&'static str; 2], built_in_vertex: &str, built_in_fragment: &str) -> Shader {
+		println!("Compiling {} shader...", names[1]);
+		let modified = newest_modification(&shader_files(names));
+		let (program, error) = match compile(display, names) {
+			Ok(program) => (program, None),
+			Err(err) => {
+				println!("{}", err);
+				let program = Program::from_source(display, built_in_vertex, built_in_fragment, None)
+					.expect("The built in shaders should compile");
+				(program, Some(err))
+			}
+		};
+
+		Shader { names, program, modified, error }
+	}
+
+	/// Compiles the shader again if its files have changed. Returns true if
+	/// anything happened, even if it didn't compile.
+	pub fn hot_load(&mut self, display: &Display) -> bool {
+		let modified = newest_modification(&shader_files(self.names));
+		if modified == self.modified {
+			return false;
+		}
+		self.modified = modified;
+
+		match compile(display, self.names) {
+			Ok(program) => {
+				println!("Recompiled the {} shader", self.names[1]);
+				self.program = program;
+				self.error = None;
+			}
+			Err(err) => {
+				println!("{}", err);
+				self.error = Some(err);
+			}
+		}
+		true
+	}
+}
+
+impl Deref for Shader {
+	type Target = Program;
+
+	fn deref(&self) -> &Program {
+		&self.program
+	}
+}
+
+fn shader_files([vertex, fragment]: [&str; 2]) -> Vec<PathBuf> {
+	let directory = Path::new(SHADER_DIRECTORY);
+	vec![
+		directory.join(vertex).with_extension("vert"),
+		directory.join(fragment).with_extension("frag"),
+	]
+}
+
+fn compile(display: &Display, names: [&str; 2]) -> Result<Program, String> {
+	let files = shader_files(names);
+	let mut sources = Vec::with_capacity(files.len());
+	for file in &files {
+		let source = fs::read_to_string(file)
+			.map_err(|err| format!("{:?}: {}", file, err))?;
+		sources.push(source);
+	}
+
+	Program::from_source(display, &sources[0], &sources[1], None)
+		.map_err(|err| format!("{} shader: {}", names[1], err))
+}
@@ -75,6 +75,19 @@ impl Sounds {
 		})
 	}
 
+	/// Creates a Sounds struct that never plays anything.
+	#[cfg(test)]
+	pub fn silent() -> Sounds {
+		let (sound_transmit, sound_recieve) = mpsc::channel();
+		std::thread::spawn(move || {
+			while sound_recieve.recv().is_ok() {}
+		});
+
+		Sounds {
+			channel: sound_transmit,
+		}
+	}
+
 	pub fn play(&self, sound: SoundId, volume: f32) {
 		match self.channel.send(SoundMessage::Play(sound, volume)) {
 			Ok(()) => (),
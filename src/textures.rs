@@ -2,21 +2,27 @@ use crate::prelude::*;
 use texture::{Texture2dArray, RawImage2d, TextureCreationError};
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::fmt;
 
 #[derive(Debug)]
 pub enum TextureLoadingError {
 	Io(std::io::Error),
-	Image(image::error::ImageError),
+	Image(image::error::ImageError, PathBuf),
 	TextureCreation(TextureCreationError),
 	InvalidConfigArgNumber(usize),
 	UnknownResourceName(usize, String),
 	DuplicateResource(usize, String),
 	InvalidResourceType(usize, String),
+	InvalidAnimation(usize),
 	InconsistantTextureSize {
 		wanted: (usize, usize),
 		got: (usize, usize),
 		file: PathBuf,
-	}
+	},
+	InvalidBlobSheetSize {
+		got: (usize, usize),
+		file: PathBuf,
+	},
 }
 
 impl From<std::io::Error> for TextureLoadingError {
@@ -31,9 +37,54 @@ impl From<TextureCreationError> for TextureLoadingError {
 	}
 }
 
-impl From<image::error::ImageError> for TextureLoadingError {
-	fn from(other: image::error::ImageError) -> Self {
-		TextureLoadingError::Image(other)
+impl TextureLoadingError {
+	/// The line in the assets file that is wrong, starting at 1 like in 
+	/// text editors, if it's about a specific line.
+	pub fn line(&self) -> Option<usize> {
+		use TextureLoadingError::*;
+		match self {
+			InvalidConfigArgNumber(line) |
+			UnknownResourceName(line, _) |
+			DuplicateResource(line, _) |
+			InvalidResourceType(line, _) |
+			InvalidAnimation(line) => Some(line + 1),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for TextureLoadingError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		use TextureLoadingError::*;
+		// Line numbers start at 1 in text editors
+		match self {
+			Io(err) => write!(f, "{}", err),
+			Image(err, file) => write!(f, "{:?}: {}", file, err),
+			TextureCreation(err) => write!(f, "{}", err),
+			InvalidConfigArgNumber(line) => 
+				write!(f, "Line {}: Expected 'name ; kind ; path'", line + 1),
+			UnknownResourceName(line, name) => 
+				write!(f, "Line {}: There is no texture called '{}'", line + 1, name),
+			DuplicateResource(line, name) => 
+				write!(f, "Line {}: '{}' is defined more than once", line + 1, name),
+			InvalidResourceType(line, kind) => 
+				write!(f, "Line {}: Unknown kind '{}'", line + 1, kind),
+			InvalidAnimation(line) => write!(
+				f, 
+				"Line {}: Expected 'name ; anim ; path ; <frames> ; <fps>fps'", 
+				line + 1,
+			),
+			InconsistantTextureSize { wanted, got, file } => write!(
+				f, 
+				"{:?} is {}x{}, but the other textures are {}x{}", 
+				file, got.0, got.1, wanted.0, wanted.1,
+			),
+			InvalidBlobSheetSize { got, file } => write!(
+				f, 
+				"{:?} is {}x{}, but blob sheets have to be {} by {} tiles", 
+				file, got.0, got.1, BLOB_COLUMNS, BLOB_ROWS,
+			),
+		}
 	}
 }
 
@@ -64,9 +115,19 @@ make_textures! {
 	Cake: "cake",
 	SadHome: "sad_home",
 	Player: "player",
+	PlayerMarker: "player_marker",
+	PlayerTwo: "player_two",
+	PlayerWalk: "player_walk",
+	PlayerTwoWalk: "player_two_walk",
 	HumanWithGoop: "human_with_goop",
 	CakeWithGoop: "cake_with_goop",
 	BucketOfGoop: "bucket_of_goop",
+	HeavyCrate: "heavy_crate",
+	Sponge: "sponge",
+	GoopDrop: "goop_drop",
+	Crumb: "crumb",
+	Sparkle: "sparkle",
+	Dust: "dust",
 	FloorMap: "floor_map",
 	GoopMap: "goop_map",
 	IceMap: "ice_map",
@@ -74,25 +135,111 @@ make_textures! {
 	Grass: "grass",
 	FlowerMap: "flower_map",
 	MossyMap: "mossy_map",
+	Plate: "plate",
+	PlatePressed: "plate_pressed",
+	DoorClosed: "door_closed",
+	DoorOpen: "door_open",
+	ConveyorLeft: "conveyor_left",
+	ConveyorRight: "conveyor_right",
+	ConveyorUp: "conveyor_up",
+	ConveyorDown: "conveyor_down",
+	Hole: "hole",
+	Heater: "heater",
+	Exit: "exit",
+	IceCracked: "ice_cracked",
+	IceMelting: "ice_melting",
+	Water: "water",
+	Puddle: "puddle",
+	OneWayLeft: "one_way_left",
+	OneWayRight: "one_way_right",
+	OneWayUp: "one_way_up",
+	OneWayDown: "one_way_down",
+	Shade: "shade",
+	UndoPrompt: "undo_prompt",
+	RestartPrompt: "restart_prompt",
+	Digit0: "digit_0",
+	Digit1: "digit_1",
+	Digit2: "digit_2",
+	Digit3: "digit_3",
+	Digit4: "digit_4",
+	Digit5: "digit_5",
+	Digit6: "digit_6",
+	Digit7: "digit_7",
+	Digit8: "digit_8",
+	Digit9: "digit_9",
+}
+
+/// How many different tiles a blob tilemap has, one for every way the 
+/// neighbours can connect. Corners only count when both of the sides next 
+/// to them connect too.
+pub const N_BLOB_TILES: usize = 47;
+/// Blob tiles come in one image, with this many tiles on every row. The 
+/// tiles are in the order of ``blob_tile_index``, starting at the top left.
+const BLOB_COLUMNS: u32 = 8;
+const BLOB_ROWS: u32 = 6;
+
+// The bits of a blob tile, for which neighbours connect to it
+const BLOB_LEFT: u8 = 1;
+const BLOB_RIGHT: u8 = 2;
+const BLOB_DOWN: u8 = 4;
+const BLOB_UP: u8 = 8;
+const BLOB_DOWN_LEFT: u8 = 16;
+const BLOB_DOWN_RIGHT: u8 = 32;
+const BLOB_UP_LEFT: u8 = 64;
+const BLOB_UP_RIGHT: u8 = 128;
+
+/// Where the layers of the texture array come from.
+enum TextureSource {
+	File(PathBuf),
+	// Split up into ``N_BLOB_TILES`` layers
+	BlobSheet(PathBuf),
+}
+
+impl TextureSource {
+	fn path(&self) -> &Path {
+		match self {
+			TextureSource::File(path) | TextureSource::BlobSheet(path) => path,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextureKind {
+	Texture,
+	Map,
+	Blob,
+	// Goes through all of its textures, and then starts over
+	Animation { fps: f32 },
 }
 
 #[derive(Clone, Copy, Debug)]
 struct TextureMetaData {
 	index: usize,
 	n_textures: usize,
+	kind: TextureKind,
 }
 
 pub struct Textures {
 	pub atlas: Texture2dArray,
+	// How many pixels wide and high every texture is
+	pub texture_size: [u32; 2],
 	metadata: Vec<TextureMetaData>,
+	// A checkerboard for things that couldn't be loaded
+	missing_index: usize,
 } 
 
 impl Textures {
-	pub fn load(path: impl AsRef<Path>, display: &Display)
+	/// Loads the textures listed in an assets.txt file. Every file that is 
+	/// needed for that is put into ``files``, even if loading fails, so 
+	/// that they can be watched for changes.
+	pub fn load(path: impl AsRef<Path>, display: &Display, files: &mut Vec<PathBuf>)
 		-> Result<Textures, TextureLoadingError> 
 	{
+		files.push(path.as_ref().to_path_buf());
+
 		let mut metadata: Vec<Option<TextureMetaData>> = vec![None; Texture::NTextures as usize];
-		let mut texture_files: Vec<PathBuf> = Vec::new();
+		let mut texture_sources: Vec<TextureSource> = Vec::new();
+		let mut n_layers = 0;
 
 		let mut parts = Vec::new();
 		let contents = fs::read_to_string(path)?;
@@ -104,12 +251,12 @@ impl Textures {
 			parts.clear();
 			line.split(';').map(|v| v.trim()).for_each(|v| parts.push(v));
 
-			let [name, kind, path] = if let &[name, kind, path] 
-				= parts.as_slice() 
-			{
-				[name, kind, path]
-			}else {
-				return Err(TextureLoadingError::InvalidConfigArgNumber(line_num));
+			// Only animations have arguments after the path
+			let (name, kind, path, args) = match *parts.as_slice() {
+				[name, kind, path] => (name, kind, path, None),
+				[name, "anim", path, n_frames, fps] => 
+					(name, "anim", path, Some((n_frames, fps))),
+				_ => return Err(TextureLoadingError::InvalidConfigArgNumber(line_num)),
 			};
 
 			let texture = string_to_texture_variant(name)
@@ -128,24 +275,55 @@ impl Textures {
 			match kind {
 				"texture" => {
 					metadata[texture as usize] = Some(TextureMetaData {
-						index: texture_files.len(),
+						index: n_layers,
 						n_textures: 1,
+						kind: TextureKind::Texture,
 					});
-					texture_files.push(path.into());
+					texture_sources.push(TextureSource::File(path.into()));
+					n_layers += 1;
 				}
 				"map" => {
 					metadata[texture as usize] = Some(TextureMetaData {
-						index: texture_files.len(),
+						index: n_layers,
 						n_textures: 8,
+						kind: TextureKind::Map,
+					});
+					for i in 0..8 {
+						texture_sources.push(TextureSource::File(
+							format!("{}{}.png", path, i).into()
+						));
+					}
+					n_layers += 8;
+				}
+				"blob" => {
+					metadata[texture as usize] = Some(TextureMetaData {
+						index: n_layers,
+						n_textures: N_BLOB_TILES,
+						kind: TextureKind::Blob,
 					});
-					texture_files.push((path.to_string() + "0.png").into());
-					texture_files.push((path.to_string() + "1.png").into());
-					texture_files.push((path.to_string() + "2.png").into());
-					texture_files.push((path.to_string() + "3.png").into());
-					texture_files.push((path.to_string() + "4.png").into());
-					texture_files.push((path.to_string() + "5.png").into());
-					texture_files.push((path.to_string() + "6.png").into());
-					texture_files.push((path.to_string() + "7.png").into());
+					texture_sources.push(TextureSource::BlobSheet(path.into()));
+					n_layers += N_BLOB_TILES;
+				}
+				"anim" => {
+					let (n_frames, fps) = args
+						.and_then(|(n_frames, fps)| Some((
+							n_frames.parse::<usize>().ok()?,
+							fps.strip_suffix("fps")?.trim().parse::<f32>().ok()?,
+						)))
+						.filter(|&(n_frames, fps)| n_frames > 0 && fps > 0.0)
+						.ok_or(TextureLoadingError::InvalidAnimation(line_num))?;
+
+					metadata[texture as usize] = Some(TextureMetaData {
+						index: n_layers,
+						n_textures: n_frames,
+						kind: TextureKind::Animation { fps },
+					});
+					for i in 0..n_frames {
+						texture_sources.push(TextureSource::File(
+							format!("{}{}.png", path, i).into()
+						));
+					}
+					n_layers += n_frames;
 				}
 				_ => return Err(TextureLoadingError::InvalidResourceType(
 					line_num, 
@@ -154,6 +332,9 @@ impl Textures {
 			}
 		}
 
+		files.extend(texture_sources.iter().map(|v| v.path().to_path_buf()));
+
+		let missing_index = n_layers;
 		let mut unwrapped_metadata = Vec::with_capacity(metadata.len());
 		for (i, element) in metadata.into_iter().enumerate() {
 			if let Some(element) = element {
@@ -161,37 +342,66 @@ impl Textures {
 			} else {
 				println!("WARNING: Incomplete assets.txt, resource number {} is not defined", i);
 				unwrapped_metadata.push(TextureMetaData {
-					index: 0,
+					index: missing_index,
 					n_textures: 1,
+					kind: TextureKind::Texture,
 				});
 			}
 		}
 
 		// Load all the textures that were queued earlier
 		let mut size = None;
-		let mut loaded_textures = Vec::with_capacity(texture_files.len());
-		for texture_file in texture_files {
-			let image = image::open(&texture_file)?.into_rgba();
-			let (width, height) = image.dimensions();
-
-			if let Some(size) = size {
-				if (width, height) != size {
-					return Err(TextureLoadingError::InconsistantTextureSize {
-						got: (width as usize, height as usize),
-						wanted: (size.0 as usize, size.1 as usize),
-						file: texture_file,
-					});
+		let mut loaded_textures = Vec::with_capacity(n_layers);
+		for source in texture_sources {
+			match source {
+				TextureSource::File(texture_file) => {
+					let image = image::open(&texture_file)
+						.map_err(|err| TextureLoadingError::Image(err, texture_file.clone()))?
+						.into_rgba();
+					let (width, height) = image.dimensions();
+					check_texture_size(&mut size, (width, height), &texture_file)?;
+
+					let raw = 
+						RawImage2d::from_raw_rgba(image.into_raw(), (width, height));
+					loaded_textures.push(raw);
+					println!("Loaded texture '{:?}'", texture_file);
 				}
-			} else {
-				size = Some((width, height));
-			}
+				TextureSource::BlobSheet(sheet_file) => {
+					let mut image = image::open(&sheet_file)
+						.map_err(|err| TextureLoadingError::Image(err, sheet_file.clone()))?
+						.into_rgba();
+					let (sheet_width, sheet_height) = image.dimensions();
+					if sheet_width % BLOB_COLUMNS != 0 || sheet_height % BLOB_ROWS != 0 {
+						return Err(TextureLoadingError::InvalidBlobSheetSize {
+							got: (sheet_width as usize, sheet_height as usize),
+							file: sheet_file,
+						});
+					}
+
+					let (width, height) = 
+						(sheet_width / BLOB_COLUMNS, sheet_height / BLOB_ROWS);
+					check_texture_size(&mut size, (width, height), &sheet_file)?;
 
-			let raw = 
-				RawImage2d::from_raw_rgba(image.into_raw(), (width, height));
-			loaded_textures.push(raw);
-			println!("Loaded texture '{:?}'", texture_file);
+					for i in 0..N_BLOB_TILES as u32 {
+						let tile = image::imageops::crop(
+							&mut image, 
+							i % BLOB_COLUMNS * width, 
+							i / BLOB_COLUMNS * height, 
+							width, 
+							height,
+						).to_image();
+						let raw = 
+							RawImage2d::from_raw_rgba(tile.into_raw(), (width, height));
+						loaded_textures.push(raw);
+					}
+					println!("Loaded blob sheet '{:?}'", sheet_file);
+				}
+			}
 		}
 
+		let (width, height) = size.unwrap_or((16, 16));
+		loaded_textures.push(missing_texture(width, height));
+
 		let texture_array = Texture2dArray::new(
 			display,
 			loaded_textures,
@@ -199,7 +409,9 @@ impl Textures {
 
 		Ok(Textures {
 			atlas: texture_array,
+			texture_size: [width, height],
 			metadata: unwrapped_metadata,
+			missing_index,
 		})
 	}
 
@@ -214,6 +426,81 @@ impl Textures {
 		} 
 	} 
 
+	/// Textures to use when assets.txt can't be loaded at all, where 
+	/// everything is missing.
+	pub fn fallback(display: &Display) -> Textures {
+		let atlas = Texture2dArray::new(display, vec![missing_texture(16, 16)])
+			.unwrap();
+
+		Textures {
+			atlas,
+			texture_size: [16, 16],
+			metadata: vec![
+				TextureMetaData {
+					index: 0,
+					n_textures: 1,
+					kind: TextureKind::Texture,
+				};
+				Texture::NTextures as usize
+			],
+			missing_index: 0,
+		}
+	}
+
+	pub fn get_missing_uv(&self) -> UVCoords {
+		UVCoords {
+			left: 0.0,
+			right: 1.0,
+			bottom: 1.0,
+			top: 0.0,
+			texture: self.missing_index as f32,
+		}
+	}
+
+	/// If the tilemap has a tile for every way its neighbours connect, so
+	/// that it should be drawn with ``get_blob_uv``.
+	pub fn is_blob(&self, texture: Texture) -> bool {
+		matches!(self.metadata[texture as usize].kind, TextureKind::Blob)
+	}
+
+	/// Like ``get_uv``, but animations are at the frame they should be at 
+	/// after ``time`` seconds.
+	pub fn get_animated_uv(&self, texture: Texture, time: f32) -> UVCoords {
+		let metadata = self.metadata[texture as usize];
+		match metadata.kind {
+			TextureKind::Animation { fps } => {
+				let frame = (time.max(0.0) * fps) as usize % metadata.n_textures;
+				UVCoords {
+					texture: (metadata.index + frame) as f32,
+					..self.get_uv(texture)
+				}
+			}
+			_ => self.get_uv(texture),
+		}
+	}
+
+	/// The tile of a blob tilemap. ``neighbours`` is laid out like in 
+	/// ``generate_tilemap_tile_graphics``, with the middle one ignored.
+	pub fn get_blob_uv(&self, texture: Texture, neighbours: [bool; 9]) -> UVCoords {
+		let metadata = self.metadata[texture as usize];
+		let bits = [
+			(3, BLOB_LEFT), (5, BLOB_RIGHT), (1, BLOB_DOWN), (7, BLOB_UP),
+			(0, BLOB_DOWN_LEFT), (2, BLOB_DOWN_RIGHT), 
+			(6, BLOB_UP_LEFT), (8, BLOB_UP_RIGHT),
+		];
+		let mask = bits.iter()
+			.filter(|&&(neighbour, _)| neighbours[neighbour])
+			.fold(0, |mask, &(_, bit)| mask | bit);
+
+		UVCoords {
+			left: 0.0,
+			right: 1.0,
+			bottom: 1.0,
+			top: 0.0,
+			texture: (metadata.index + blob_tile_index(mask)) as f32,
+		}
+	}
+
 	pub fn get_tilemap_uv(&self, texture: Texture, horizontal: bool, vertical: bool, diagonal: bool) -> UVCoords {
 		let metadata = self.metadata[texture as usize];
 
@@ -240,6 +527,63 @@ impl Textures {
 	} 
 }
 
+/// A magenta and black checkerboard, that is hard to miss.
+fn missing_texture(width: u32, height: u32) -> RawImage2d<'static, u8> {
+	let mut data = Vec::with_capacity((width * height * 4) as usize);
+	for y in 0..height {
+		for x in 0..width {
+			if (x * 4 / width) % 2 == (y * 4 / height) % 2 {
+				data.extend_from_slice(&[255, 0, 255, 255]);
+			} else {
+				data.extend_from_slice(&[0, 0, 0, 255]);
+			}
+		}
+	}
+	RawImage2d::from_raw_rgba(data, (width, height))
+}
+
+fn check_texture_size(size: &mut Option<(u32, u32)>, got: (u32, u32), file: &Path) 
+	-> Result<(), TextureLoadingError> 
+{
+	match *size {
+		Some(wanted) if wanted != got => 
+			Err(TextureLoadingError::InconsistantTextureSize {
+				got: (got.0 as usize, got.1 as usize),
+				wanted: (wanted.0 as usize, wanted.1 as usize),
+				file: file.to_path_buf(),
+			}),
+		Some(_) => Ok(()),
+		None => {
+			*size = Some(got);
+			Ok(())
+		}
+	}
+}
+
+/// Removes the corners that don't count, because one of the sides next to 
+/// them doesn't connect.
+fn reduce_blob_mask(mut mask: u8) -> u8 {
+	for &(corner, side_a, side_b) in &[
+		(BLOB_DOWN_LEFT, BLOB_DOWN, BLOB_LEFT),
+		(BLOB_DOWN_RIGHT, BLOB_DOWN, BLOB_RIGHT),
+		(BLOB_UP_LEFT, BLOB_UP, BLOB_LEFT),
+		(BLOB_UP_RIGHT, BLOB_UP, BLOB_RIGHT),
+	] {
+		if mask & side_a == 0 || mask & side_b == 0 {
+			mask &= !corner;
+		}
+	}
+	mask
+}
+
+/// Where in a blob sheet the tile for a set of connecting neighbours is. 
+/// The tiles are sorted by their reduced mask, so the first one connects to 
+/// nothing and the last one to everything.
+pub fn blob_tile_index(mask: u8) -> usize {
+	let mask = reduce_blob_mask(mask);
+	(0..mask).filter(|&other| reduce_blob_mask(other) == other).count()
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct UVCoords {
 	pub left: f32,
@@ -262,3 +606,20 @@ impl UVCoords {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn blob_tiles_fill_the_sheet() {
+		let mut indices: Vec<usize> = (0..=255).map(blob_tile_index).collect();
+		indices.sort();
+		indices.dedup();
+		assert_eq!(indices, (0..N_BLOB_TILES).collect::<Vec<_>>());
+		assert_eq!(blob_tile_index(0), 0);
+		assert_eq!(blob_tile_index(255), N_BLOB_TILES - 1);
+		// A corner without its sides is the same as nothing
+		assert_eq!(blob_tile_index(BLOB_UP_LEFT), 0);
+	}
+}
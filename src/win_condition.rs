@@ -0,0 +1,144 @@
+use crate::prelude::*;
+use crate::level::{Tile, WallKind, EntityKind, LossReason};
+use std::rc::Rc;
+
+/// Something a level wants you to do. A level is won once all of its
+/// conditions are met at the same time.
+pub trait WinCondition {
+	fn is_met(&self, level: &Level) -> bool;
+
+	/// If the condition can never be met anymore. This is only checked once
+	/// everything has stopped moving.
+	fn has_failed(&self, _level: &Level) -> Option<LossReason> {
+		None
+	}
+
+	/// Limits don't count as goals of their own, they just restrict how
+	/// the other conditions can be met.
+	fn is_limit(&self) -> bool {
+		false
+	}
+
+	/// How many moves the level can be beaten in at most, if this limits it.
+	fn move_limit(&self) -> Option<u32> {
+		None
+	}
+
+	fn description(&self) -> String;
+}
+
+/// Parses the arguments of a ``$ goal`` metadata line.
+///
+/// * ``humans`` Every human has to get home. This is the goal if a level
+///   doesn't have any other.
+/// * ``cake`` Every sad home has to be fed with cake.
+/// * ``clean`` There can't be any goop left on the floor.
+/// * ``exit`` Every player has to stand on an exit.
+/// * ``moves <n>`` The other goals have to be met within ``n`` moves.
+pub fn parse<'a>(mut args: impl Iterator<Item = &'a str>)
+	-> Result<Rc<dyn WinCondition>, String>
+{
+	let condition: Rc<dyn WinCondition> = match args.next() {
+		Some("humans") => Rc::new(HumansHome),
+		Some("cake") => Rc::new(SadHomesFed),
+		Some("clean") => Rc::new(GoopCleared),
+		Some("exit") => Rc::new(ReachExit),
+		Some("moves") => match args.next().map(|v| v.parse::<u32>()) {
+			Some(Ok(n_moves)) => Rc::new(WithinMoves(n_moves)),
+			_ => return Err("Expected '$ goal moves <n>'".to_string()),
+		},
+		Some(other) => return Err(format!("Unknown goal '{}'", other)),
+		None => return Err("Expected a goal".to_string()),
+	};
+
+	if args.next().is_some() {
+		return Err("Too many arguments for a goal".to_string());
+	}
+
+	Ok(condition)
+}
+
+pub struct HumansHome;
+
+impl WinCondition for HumansHome {
+	fn is_met(&self, level: &Level) -> bool {
+		// A level without any humans shouldn't be won before it even starts
+		level.data.n_humans == 0 && 
+			level.data.tiles.buffer.contains(&Tile::Wall(WallKind::HappyHome))
+	}
+
+	fn has_failed(&self, level: &Level) -> Option<LossReason> {
+		level.find_deadlock()
+	}
+
+	fn description(&self) -> String {
+		"Get every human home".to_string()
+	}
+}
+
+pub struct SadHomesFed;
+
+impl WinCondition for SadHomesFed {
+	fn is_met(&self, level: &Level) -> bool {
+		!level.data.tiles.buffer.contains(&Tile::SadHome)
+	}
+
+	fn description(&self) -> String {
+		"Feed every sad home some cake".to_string()
+	}
+}
+
+pub struct GoopCleared;
+
+impl WinCondition for GoopCleared {
+	fn is_met(&self, level: &Level) -> bool {
+		!level.data.tiles.buffer.iter()
+			.any(|&v| v == Tile::FloorWithGoop || v == Tile::IceWithGoop)
+	}
+
+	fn description(&self) -> String {
+		"Clean up all the goop".to_string()
+	}
+}
+
+pub struct ReachExit;
+
+impl WinCondition for ReachExit {
+	fn is_met(&self, level: &Level) -> bool {
+		level.data.entities.values()
+			.filter(|v| v.kind == EntityKind::Player)
+			.all(|v| level.data.tiles.get_tile(v.pos) == Some(Tile::Exit))
+	}
+
+	fn description(&self) -> String {
+		"Get to the exit".to_string()
+	}
+}
+
+pub struct WithinMoves(pub u32);
+
+impl WinCondition for WithinMoves {
+	fn is_met(&self, level: &Level) -> bool {
+		level.data.turn <= self.0
+	}
+
+	fn has_failed(&self, level: &Level) -> Option<LossReason> {
+		if level.data.turn >= self.0 {
+			Some(LossReason::OutOfMoves)
+		} else {
+			None
+		}
+	}
+
+	fn is_limit(&self) -> bool {
+		true
+	}
+
+	fn move_limit(&self) -> Option<u32> {
+		Some(self.0)
+	}
+
+	fn description(&self) -> String {
+		format!("Do it in at most {} moves", self.0)
+	}
+}